@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use crate::{
+    GameState, Human, Piece,
+    board::Board,
+    check_move,
+    eval::Weights,
+    minimax::{Budget, Level, MAX_PLIES, Minimax, WIN_SCORE},
+    pos_as_string, print_game, print_result, string_as_pos,
+};
+
+/// Plays `moves` from the start of a game, then prints how the smart bot scores every legal move
+/// of the position, the line of play it expects and how hard it had to work for it.
+pub fn analyze(moves: &[String], budget: Budget, weights: Weights) {
+    let mut game = GameState::new(Arc::new(Human), Arc::new(Human));
+    for pos in moves {
+        let pos = string_as_pos(pos).unwrap_or_else(|| panic!("{pos} is not a position"));
+        if let Err(reason) = check_move(&game.game, &game.active, pos) {
+            panic!("Can't play {}: {reason}", pos_as_string(&pos));
+        }
+        game.manual_turn(pos.0, pos.1);
+    }
+
+    print_game(&game.game, &game.active, None);
+    if game.is_complete() {
+        print_result(&game);
+        return;
+    }
+    if game.turn == Piece::None {
+        game.turn = Piece::X;
+    }
+
+    let board = Board::new(&game.game, game.turn, game.active);
+    let analysis = Minimax::new(budget, Level::EXPERT, weights).analyze(&board, true);
+
+    println!("{} to move, scores from their point of view:", game.turn);
+    for (pos, score) in &analysis.scores {
+        println!("  {}  {}", pos_as_string(pos), score_as_string(*score));
+    }
+    let pv = analysis.pv.iter().map(pos_as_string).collect::<Vec<_>>();
+    println!("Best line: {}", pv.join(" "));
+    println!(
+        "Depth {}, {} nodes in {:.2}s ({:.0} nodes/s)",
+        analysis.depth,
+        analysis.nodes,
+        analysis.elapsed.as_secs_f64(),
+        analysis.nodes_per_second()
+    );
+    println!(
+        "Branching factor {:.2}, {:.0}% of cutoffs by the first move",
+        analysis.branching_factor(),
+        analysis.first_cutoff_rate() * 100.0
+    );
+}
+
+/// Shows a score, with forced wins and losses as the number of moves the winner needs.
+fn score_as_string(score: isize) -> String {
+    if score > WIN_SCORE - MAX_PLIES {
+        format!("win in {}", (WIN_SCORE - score + 1) / 2)
+    } else if score < -(WIN_SCORE - MAX_PLIES) {
+        format!("loss in {}", (WIN_SCORE + score) / 2)
+    } else {
+        format!("{score:+}")
+    }
+}
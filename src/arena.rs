@@ -0,0 +1,246 @@
+use std::time::{Duration, Instant};
+
+use crate::{Action, GameState, Piece, Player};
+
+/// How sure a match with `--sprt` must be before stopping: the chance of calling the first bot
+/// better when it isn't, and of missing that it is.
+const SPRT_ALPHA: f64 = 0.05;
+const SPRT_BETA: f64 = 0.05;
+/// The z-score of the error bars, for 95% confidence.
+const Z_95: f64 = 1.96;
+
+/// The results so far, from the first bot's point of view.
+#[derive(Default)]
+pub struct Results {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Results {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    /// The points per game, with a draw worth half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    /// How much the points of a single game vary.
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / self.games().max(1) as f64
+    }
+
+    /// The Elo difference the score suggests, with the difference at each end of its 95% error
+    /// bars. The bars are a Wilson interval, which stays wide after a clean sweep, where the
+    /// results alone don't vary at all. Draws make the bars a little wider than they need to be.
+    pub fn elo(&self) -> (f64, f64, f64) {
+        let (score, games) = (self.score(), self.games().max(1) as f64);
+        let z2 = Z_95 * Z_95 / games;
+        let centre = (score + z2 / 2.0) / (1.0 + z2);
+        let margin = Z_95 / (1.0 + z2) * (score * (1.0 - score) / games + z2 / games / 4.0).sqrt();
+        (
+            elo_from_score(score),
+            elo_from_score((centre - margin).max(0.0)),
+            elo_from_score((centre + margin).min(1.0)),
+        )
+    }
+
+    /// The log-likelihood ratio of the first bot being `elo1` stronger rather than `elo0`, using
+    /// the normal approximation of the results.
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score_from_elo(elo0), score_from_elo(elo1));
+        let games = self.games() as f64;
+        (s1 - s0) * (2.0 * self.score() * games - games * (s0 + s1)) / (2.0 * variance)
+    }
+}
+
+/// The expected points per game of a player `elo` stronger than its opponent.
+pub fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// The Elo difference that gives `score` points per game, which is infinite for a clean sweep.
+pub fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Formats an Elo difference as a whole number with its sign, without showing -0.
+fn elo_as_string(elo: f64) -> String {
+    format!("{:+.0}", elo.round() + 0.0)
+}
+
+/// The time a player took over all of its moves.
+#[derive(Default)]
+struct Clock {
+    moves: u32,
+    time: Duration,
+}
+
+impl Clock {
+    fn average(&self) -> Duration {
+        self.time / self.moves.max(1)
+    }
+}
+
+/// Plays up to `games` games between `a` and `b` without showing them, with `a` as X in the first
+/// game and the bots swapping sides after every game, then reports how `a` did. With `sprt`, the
+/// match stops as soon as it is clear whether `a` is about `sprt.0` or `sprt.1` Elo stronger.
+pub fn play_match(names: [&str; 2], a: Player, b: Player, games: u32, sprt: Option<(f64, f64)>) {
+    let mut results = Results::default();
+    let mut clocks = [Clock::default(), Clock::default()];
+    let bounds = (
+        (SPRT_BETA / (1.0 - SPRT_ALPHA)).ln(),
+        ((1.0 - SPRT_BETA) / SPRT_ALPHA).ln(),
+    );
+
+    for game in 1..=games {
+        let a_side = if game % 2 == 1 { Piece::X } else { Piece::O };
+        let [a_clock, b_clock] = &mut clocks;
+        let winner = if a_side == Piece::X {
+            play_game(a.clone(), b.clone(), [a_clock, b_clock])
+        } else {
+            play_game(b.clone(), a.clone(), [b_clock, a_clock])
+        };
+
+        let outcome = if winner == a_side {
+            results.wins += 1;
+            format!("{} wins", names[0])
+        } else if winner == Piece::None {
+            results.draws += 1;
+            "draw".to_string()
+        } else {
+            results.losses += 1;
+            format!("{} wins", names[1])
+        };
+        println!(
+            "Game {game}/{games} ({} as {a_side}): {outcome}   +{} -{} ={}",
+            names[0], results.wins, results.losses, results.draws
+        );
+
+        if let Some((elo0, elo1)) = sprt {
+            let llr = results.llr(elo0, elo1);
+            if llr <= bounds.0 {
+                println!(
+                    "SPRT: {} is not {elo1} Elo stronger (LLR {llr:.2})",
+                    names[0]
+                );
+                break;
+            }
+            if llr >= bounds.1 {
+                println!("SPRT: {} is {elo1} Elo stronger (LLR {llr:.2})", names[0]);
+                break;
+            }
+        }
+    }
+
+    let (elo, low, high) = results.elo();
+    let [elo, low, high] = [elo, low, high].map(elo_as_string);
+    println!();
+    println!(
+        "{} against {}: {} wins, {} losses, {} draws in {} games",
+        names[0],
+        names[1],
+        results.wins,
+        results.losses,
+        results.draws,
+        results.games()
+    );
+    println!(
+        "Elo difference: {elo} (95% between {low} and {high}), score {:.1}%",
+        results.score() * 100.0
+    );
+    for (name, clock) in names.iter().zip(&clocks) {
+        println!("{name}: {:.2?} per move on average", clock.average());
+    }
+}
+
+/// Plays one game between `x` and `o`, timing their moves on their `clocks`, and returns who won,
+/// or `Piece::None` for a draw. A player that makes an illegal move or resigns loses the game.
+fn play_game(x: Player, o: Player, clocks: [&mut Clock; 2]) -> Piece {
+    let mut game = GameState::new(x, o);
+    let [x_clock, o_clock] = clocks;
+
+    while !game.is_complete() {
+        let (player, clock) = if game.turn == Piece::O {
+            (&game.player_2, &mut *o_clock)
+        } else {
+            (&game.player_1, &mut *x_clock)
+        };
+
+        let start = Instant::now();
+        let action = player.play_quietly(&game.game, &game.turn, game.active);
+        clock.time += start.elapsed();
+
+        match action {
+            Some(Action::Move((x, y))) if game.manual_turn(x, y) => clock.moves += 1,
+            // bots don't chat, but it isn't a move if they do
+            Some(Action::Say(_)) => {}
+            Some(Action::Move(_)) | Some(Action::Resign) => game.resigned = game.turn,
+            // a bot only passes when there is no legal move, which can't be held against it
+            None => break,
+        }
+    }
+
+    game.won()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_matches_score() {
+        assert_eq!(elo_from_score(0.5), 0.0);
+        assert!((score_from_elo(elo_from_score(0.75)) - 0.75).abs() < 1e-9);
+        assert!((elo_from_score(0.75) - 190.85).abs() < 0.01);
+        assert_eq!(elo_as_string(elo_from_score(0.5)), "+0");
+        assert_eq!(elo_as_string(-0.4), "+0");
+    }
+
+    #[test]
+    fn error_bars_stay_wide_after_a_sweep() {
+        let sweep = Results {
+            wins: 4,
+            losses: 0,
+            draws: 0,
+        };
+        let (elo, low, high) = sweep.elo();
+        assert_eq!((elo, high), (f64::INFINITY, f64::INFINITY));
+        assert!(low.is_finite() && low < 100.0);
+
+        let even = Results {
+            wins: 10,
+            losses: 10,
+            draws: 0,
+        };
+        let (elo, low, high) = even.elo();
+        assert!(elo.abs() < 1e-9 && low < -100.0 && high > 100.0);
+    }
+
+    #[test]
+    fn llr_favours_the_closer_hypothesis() {
+        let winning = Results {
+            wins: 60,
+            losses: 30,
+            draws: 10,
+        };
+        assert!(winning.llr(0.0, 50.0) > 0.0);
+
+        let even = Results {
+            wins: 45,
+            losses: 45,
+            draws: 10,
+        };
+        assert!(even.llr(0.0, 50.0) < 0.0);
+    }
+}
@@ -0,0 +1,87 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const NONCE_LEN: usize = 16;
+pub const PROOF_LEN: usize = 32;
+/// The length of the MAC at the end of every message on a connection with a password.
+pub const TAG_LEN: usize = 16;
+
+const SALT: &[u8] = b"tut3 game password";
+/// Makes guessing the password from a recorded handshake slow.
+const ROUNDS: u32 = 100_000;
+
+pub type Nonce = [u8; NONCE_LEN];
+
+/// Who made a proof or sent a message, so that nothing can be reflected back to its sender.
+pub const JOINING: u8 = b'j';
+pub const HOST: u8 = b'h';
+
+/// A secret derived from a game password. Everyone who knows the password derives the same key.
+///
+/// A connection is authenticated with a challenge and response: the joining side sends a nonce in
+/// its `Hello`, the host answers with a nonce of its own, and then both sides prove they know the
+/// key with a MAC over both nonces, the joining side first. Every message after that carries a
+/// MAC made with a key for that one connection, so messages cannot be changed, made up or replayed
+/// by anyone without the password.
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub fn derive(password: &str) -> Key {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), SALT, ROUNDS, &mut key);
+        Key(key)
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac
+    }
+
+    /// What `sender` sends to prove it knows the password.
+    pub fn proof(&self, sender: u8, joining: &Nonce, host: &Nonce) -> [u8; PROOF_LEN] {
+        self.mac(&[b"proof", &[sender], joining, host])
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    pub fn check_proof(&self, sender: u8, joining: &Nonce, host: &Nonce, proof: &[u8]) -> bool {
+        self.mac(&[b"proof", &[sender], joining, host])
+            .verify_slice(proof)
+            .is_ok()
+    }
+
+    /// The key protecting the messages of one connection.
+    pub fn session(&self, joining: &Nonce, host: &Nonce) -> Key {
+        Key(self
+            .mac(&[b"session", joining, host])
+            .finalize()
+            .into_bytes()
+            .into())
+    }
+
+    /// The MAC of the `counter`th message sent by the side called `sender`.
+    pub fn tag(&self, sender: u8, counter: u64, message: &[u8]) -> [u8; TAG_LEN] {
+        let tag = self
+            .mac(&[&[sender], &counter.to_be_bytes(), message])
+            .finalize()
+            .into_bytes();
+        tag[..TAG_LEN].try_into().unwrap()
+    }
+
+    pub fn check_tag(&self, sender: u8, counter: u64, message: &[u8], tag: &[u8]) -> bool {
+        self.mac(&[&[sender], &counter.to_be_bytes(), message])
+            .verify_truncated_left(tag)
+            .is_ok()
+    }
+}
+
+pub fn nonce() -> Nonce {
+    rand::random()
+}
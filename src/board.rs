@@ -0,0 +1,623 @@
+use std::ops::{Deref, DerefMut};
+
+use rand::Rng;
+
+use crate::{Game, Piece, WINS};
+
+/// The cells of a sub-board, or the sub-boards of the whole board, as a 9-bit mask. Bit `x * 3 + y`
+/// is the cell or sub-board at `(x, y)`, with the same coordinates as `WINS`.
+pub type Mask = u16;
+
+pub const FULL: Mask = 0x1ff;
+
+/// `WINS` as masks.
+pub const LINES: [Mask; 8] = {
+    let mut lines = [0; 8];
+    let mut i = 0;
+    while i < 8 {
+        let mut j = 0;
+        while j < 3 {
+            let (x, y) = WINS[i][j];
+            lines[i] |= 1 << (x * 3 + y);
+            j += 1;
+        }
+        i += 1;
+    }
+    lines
+};
+
+/// Whether each of the 512 possible masks contains a whole line.
+const HAS_LINE: [bool; 512] = {
+    let mut has_line = [false; 512];
+    let mut mask = 0;
+    while mask < 512 {
+        let mut i = 0;
+        while i < 8 {
+            if mask & LINES[i] == LINES[i] {
+                has_line[mask as usize] = true;
+            }
+            i += 1;
+        }
+        mask += 1;
+    }
+    has_line
+};
+
+/// For each of the 512 possible masks, the lines it has a bit on, as bit `i` for `LINES[i]`.
+pub const LINES_TOUCHED: [u8; 512] = {
+    let mut touched = [0; 512];
+    let mut mask = 0;
+    while mask < 512 {
+        let mut i = 0;
+        while i < 8 {
+            if mask & LINES[i] != 0 {
+                touched[mask as usize] |= 1 << i;
+            }
+            i += 1;
+        }
+        mask += 1;
+    }
+    touched
+};
+
+/// For each of the 512 possible masks, the lines it has exactly two bits on, in the same form as
+/// `LINES_TOUCHED`.
+pub const PAIRS: [u8; 512] = {
+    let mut pairs = [0; 512];
+    let mut mask = 0;
+    while mask < 512 {
+        let mut i = 0;
+        while i < 8 {
+            if (mask & LINES[i]).count_ones() == 2 {
+                pairs[mask as usize] |= 1 << i;
+            }
+            i += 1;
+        }
+        mask += 1;
+    }
+    pairs
+};
+
+/// The number of ways the nine cells of a sub-board can be filled with X, O or nothing.
+pub const SUB_STATES: usize = 19_683;
+
+/// For each of the 512 possible masks, its bits read as base-3 digits. A sub-board's state is
+/// `TERNARY[x] + 2 * TERNARY[o]`, so cell `i` is the base-3 digit for `3^i`: 0 if empty, 1 for X
+/// and 2 for O.
+const TERNARY: [u16; 512] = {
+    let mut ternary = [0; 512];
+    let mut mask = 0;
+    while mask < 512 {
+        let (mut cell, mut power) = (0, 1);
+        while cell < 9 {
+            if mask & (1 << cell) != 0 {
+                ternary[mask] += power;
+            }
+            power *= 3;
+            cell += 1;
+        }
+        mask += 1;
+    }
+    ternary
+};
+
+/// Everything the bots need to know about one sub-board, worked out ahead of time for all of its
+/// `SUB_STATES` states.
+#[derive(Clone, Copy)]
+pub struct SubInfo {
+    /// Who has a line here, like `subgame_won`.
+    pub winner: Piece,
+    /// Whether nobody has won and nobody can any more, like `subgame_is_draw`.
+    pub blocked: bool,
+    /// How many lines X and O each have two pieces in, with the third cell empty.
+    pub threats: [u8; 2],
+    /// Who would win the sub-board as a game of tic-tac-toe on its own, if both sides played
+    /// perfectly: 1 for X, -1 for O and 0 for a draw. The first is with X to move, the second
+    /// with O to move. Ultimate tic-tac-toe doesn't take turns inside one sub-board, so this is
+    /// only a hint at who controls it.
+    pub value: [i8; 2],
+}
+
+impl SubInfo {
+    /// The state of the sub-board with X on the cells of `x` and O on the cells of `o`.
+    pub fn of(x: Mask, o: Mask) -> &'static SubInfo {
+        &SUB_INFO[TERNARY[x as usize] as usize + 2 * TERNARY[o as usize] as usize]
+    }
+
+    /// The open two-in-a-rows of `piece` minus those of its opponent.
+    pub fn threat_balance(&self, piece: Piece) -> isize {
+        let balance = self.threats[0] as isize - self.threats[1] as isize;
+        if piece == Piece::O { -balance } else { balance }
+    }
+}
+
+/// `SubInfo` for every state of a sub-board. Placing a piece only ever raises the state number, so
+/// going through the states from the highest down, the states after each move are already known
+/// when `value` needs them.
+static SUB_INFO: [SubInfo; SUB_STATES] = {
+    let mut table = [SubInfo {
+        winner: Piece::None,
+        blocked: false,
+        threats: [0; 2],
+        value: [0; 2],
+    }; SUB_STATES];
+
+    let mut state = SUB_STATES;
+    while state > 0 {
+        state -= 1;
+
+        let (mut x, mut o, mut rest, mut cell) = (0, 0, state, 0);
+        while cell < 9 {
+            match rest % 3 {
+                1 => x |= 1 << cell,
+                2 => o |= 1 << cell,
+                _ => {}
+            }
+            rest /= 3;
+            cell += 1;
+        }
+
+        let info = &mut table[state];
+        info.threats = [
+            (PAIRS[x] & !LINES_TOUCHED[o]).count_ones() as u8,
+            (PAIRS[o] & !LINES_TOUCHED[x]).count_ones() as u8,
+        ];
+        if HAS_LINE[x] {
+            info.winner = Piece::X;
+            info.value = [1, 1];
+            continue;
+        }
+        if HAS_LINE[o] {
+            info.winner = Piece::O;
+            info.value = [-1, -1];
+            continue;
+        }
+        if LINES_TOUCHED[x] & LINES_TOUCHED[o] == 0xff {
+            info.blocked = true;
+            continue;
+        }
+
+        // X picks the move best for X and O the one best for O. A sub-board that isn't blocked
+        // always has an empty cell.
+        let (mut best_x, mut best_o) = (-1, 1);
+        let (mut cell, mut power) = (0, 1);
+        while cell < 9 {
+            if (x | o) & (1 << cell) == 0 {
+                let after_x = table[state + power].value[1];
+                let after_o = table[state + 2 * power].value[0];
+                if after_x > best_x {
+                    best_x = after_x;
+                }
+                if after_o < best_o {
+                    best_o = after_o;
+                }
+            }
+            power *= 3;
+            cell += 1;
+        }
+        table[state].value = [best_x, best_o];
+    }
+    table
+};
+
+pub fn has_line(mask: Mask) -> bool {
+    HAS_LINE[mask as usize]
+}
+
+/// The random numbers behind Zobrist hashing: one for each piece on each of the 81 cells, one for
+/// each sub-board that can be the active one, and one for when it's O's turn.
+struct Keys {
+    cells: [[u64; 2]; 81],
+    active: [u64; 9],
+    o_to_move: u64,
+}
+
+impl Keys {
+    /// Generates the keys with splitmix64 from a fixed seed, so every run agrees on them.
+    const fn new() -> Keys {
+        let mut state: u64 = 0x7475_7433;
+
+        let mut cells = [[0; 2]; 81];
+        let mut i = 0;
+        while i < 81 {
+            cells[i] = [splitmix64(&mut state), splitmix64(&mut state)];
+            i += 1;
+        }
+        let mut active = [0; 9];
+        let mut i = 0;
+        while i < 9 {
+            active[i] = splitmix64(&mut state);
+            i += 1;
+        }
+
+        Keys {
+            cells,
+            active,
+            o_to_move: splitmix64(&mut state),
+        }
+    }
+
+    fn cell(&self, (x, y): (usize, usize), piece: Piece) -> u64 {
+        match piece {
+            Piece::X => self.cells[x * 9 + y][0],
+            Piece::O => self.cells[x * 9 + y][1],
+            Piece::None => 0,
+        }
+    }
+
+    fn active(&self, active: Option<usize>) -> u64 {
+        active.map_or(0, |sub| self.active[sub])
+    }
+
+    fn turn(&self, turn: Piece) -> u64 {
+        if turn == Piece::O { self.o_to_move } else { 0 }
+    }
+}
+
+const KEYS: Keys = Keys::new();
+
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The legal moves of a position, without allocating.
+pub struct MoveList {
+    moves: [(usize, usize); 81],
+    len: usize,
+}
+
+impl Deref for MoveList {
+    type Target = [(usize, usize)];
+
+    fn deref(&self) -> &[(usize, usize)] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [(usize, usize)] {
+        &mut self.moves[..self.len]
+    }
+}
+
+/// What `Board::undo_move` needs to take a move back.
+pub struct Undo {
+    pos: (usize, usize),
+    active: Option<usize>,
+    turn: Piece,
+}
+
+/// A position as the bots see it: a mask of each side's pieces for every sub-board, plus masks of
+/// which sub-boards are won or blocked, so that checking for wins and draws is a few lookups
+/// instead of a walk over `WINS`. It is cheap to copy and keeps its Zobrist hash up to date as
+/// moves are played. Sub-board `x1 * 3 + y1` holds the cells of `game[x1][y1]`.
+#[derive(Clone, Copy)]
+pub struct Board {
+    x: [Mask; 9],
+    o: [Mask; 9],
+    won_x: Mask,
+    won_o: Mask,
+    /// Sub-boards that nobody has won and nobody can win any more.
+    blocked: Mask,
+    active: Option<usize>,
+    turn: Piece,
+    hash: u64,
+}
+
+impl Board {
+    pub fn new(game: &Game, turn: Piece, active: Option<(usize, usize)>) -> Board {
+        let active = active.map(|(x, y)| x * 3 + y);
+        let mut board = Board {
+            x: [0; 9],
+            o: [0; 9],
+            won_x: 0,
+            won_o: 0,
+            blocked: 0,
+            active,
+            turn,
+            hash: KEYS.active(active) ^ KEYS.turn(turn),
+        };
+
+        for x in 0..9 {
+            for y in 0..9 {
+                let piece = game[x / 3][y / 3][x % 3][y % 3];
+                let (sub, bit) = ((x / 3) * 3 + y / 3, 1 << ((x % 3) * 3 + y % 3));
+                match piece {
+                    Piece::X => board.x[sub] |= bit,
+                    Piece::O => board.o[sub] |= bit,
+                    Piece::None => {}
+                }
+                board.hash ^= KEYS.cell((x, y), piece);
+            }
+        }
+        for sub in 0..9 {
+            board.update(sub);
+        }
+
+        board
+    }
+
+    pub fn turn(&self) -> Piece {
+        self.turn
+    }
+
+    pub fn active(&self) -> Option<(usize, usize)> {
+        self.active.map(|sub| (sub / 3, sub % 3))
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// The cells of sub-board `sub` that `piece` has played in.
+    pub fn pieces(&self, sub: usize, piece: Piece) -> Mask {
+        match piece {
+            Piece::X => self.x[sub],
+            Piece::O => self.o[sub],
+            Piece::None => 0,
+        }
+    }
+
+    /// What is known about sub-board `sub`.
+    pub fn sub_info(&self, sub: usize) -> &'static SubInfo {
+        SubInfo::of(self.x[sub], self.o[sub])
+    }
+
+    /// The sub-boards won by `piece`.
+    pub fn won(&self, piece: Piece) -> Mask {
+        match piece {
+            Piece::X => self.won_x,
+            Piece::O => self.won_o,
+            Piece::None => 0,
+        }
+    }
+
+    /// Whether `piece` playing at `(x, y)` would give it a line in that sub-board.
+    pub fn completes_line(&self, (x, y): (usize, usize), piece: Piece) -> bool {
+        let sub = (x / 3) * 3 + y / 3;
+        let cell = (x % 3) * 3 + y % 3;
+        has_line(self.pieces(sub, piece) | 1 << cell)
+    }
+
+    /// The sub-boards nobody has won and nobody can win any more.
+    pub fn blocked(&self) -> Mask {
+        self.blocked
+    }
+
+    /// Who has won the whole board, like `game_won`.
+    pub fn winner(&self) -> Piece {
+        if has_line(self.won_x) {
+            Piece::X
+        } else if has_line(self.won_o) {
+            Piece::O
+        } else {
+            Piece::None
+        }
+    }
+
+    /// Whether the game is a draw, like `game_is_draw`: every line of the whole board goes through
+    /// a blocked sub-board, or nobody has won and there is nowhere left to play.
+    pub fn is_draw(&self) -> bool {
+        LINES_TOUCHED[self.blocked as usize] == 0xff
+            || (self.open() == 0 && self.winner() == Piece::None)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.is_draw() || self.winner() != Piece::None
+    }
+
+    /// The sub-boards that can still be played in.
+    fn open(&self) -> Mask {
+        FULL & !(self.won_x | self.won_o | self.blocked)
+    }
+
+    /// The legal moves, sub-board by sub-board.
+    pub fn moves(&self) -> MoveList {
+        let mut list = MoveList {
+            moves: [(0, 0); 81],
+            len: 0,
+        };
+
+        let mut subs = match self.active {
+            Some(sub) => self.open() & (1 << sub),
+            None => self.open(),
+        };
+        while subs != 0 {
+            let sub = subs.trailing_zeros() as usize;
+            subs &= subs - 1;
+
+            let mut cells = FULL & !(self.x[sub] | self.o[sub]);
+            while cells != 0 {
+                let cell = cells.trailing_zeros() as usize;
+                cells &= cells - 1;
+
+                list.moves[list.len] = ((sub / 3) * 3 + cell / 3, (sub % 3) * 3 + cell % 3);
+                list.len += 1;
+            }
+        }
+
+        list
+    }
+
+    /// A legal move picked at random, with every move equally likely, or `None` if there are none.
+    /// This is quicker than picking one from `moves`.
+    pub fn random_move(&self, rng: &mut impl Rng) -> Option<(usize, usize)> {
+        let empty = |sub: usize| FULL & !(self.x[sub] | self.o[sub]);
+        let pick = |sub: usize, mut cells: Mask, n: u32| {
+            for _ in 0..n {
+                cells &= cells - 1;
+            }
+            let cell = cells.trailing_zeros() as usize;
+            ((sub / 3) * 3 + cell / 3, (sub % 3) * 3 + cell % 3)
+        };
+
+        if let Some(sub) = self.active {
+            let cells = empty(sub);
+            return Some(pick(sub, cells, rng.random_range(0..cells.count_ones())));
+        }
+
+        let open = self.open();
+        let subs = || (0..9).filter(move |sub| open & (1 << sub) != 0);
+        let count = subs().map(|sub| empty(sub).count_ones()).sum::<u32>();
+        if count == 0 {
+            return None;
+        }
+
+        let mut n = rng.random_range(0..count);
+        for sub in subs() {
+            let cells = empty(sub);
+            if n < cells.count_ones() {
+                return Some(pick(sub, cells, n));
+            }
+            n -= cells.count_ones();
+        }
+        unreachable!()
+    }
+
+    /// The position after a legal move, leaving this one as it is.
+    pub fn play(&self, pos: (usize, usize)) -> Board {
+        let mut next = *self;
+        next.apply_move(pos);
+        next
+    }
+
+    /// Plays a legal move in place, following the same rules as `GameState::manual_turn`. The
+    /// returned `Undo` takes it back with `undo_move`, so a search can walk the whole tree with one
+    /// board.
+    pub fn apply_move(&mut self, (x, y): (usize, usize)) -> Undo {
+        let undo = Undo {
+            pos: (x, y),
+            active: self.active,
+            turn: self.turn,
+        };
+        let sub = (x / 3) * 3 + y / 3;
+        let cell = (x % 3) * 3 + y % 3;
+
+        match self.turn {
+            Piece::X => self.x[sub] |= 1 << cell,
+            Piece::O => self.o[sub] |= 1 << cell,
+            Piece::None => {}
+        }
+        self.update(sub);
+
+        // the cell played in picks the next sub-board
+        self.active = (self.open() & (1 << cell) != 0).then_some(cell);
+        if self.is_over() {
+            self.active = None;
+        } else {
+            self.turn = undo.turn.other();
+        }
+
+        self.rehash(&undo);
+        undo
+    }
+
+    /// Takes back the last move played with `apply_move`.
+    pub fn undo_move(&mut self, undo: Undo) {
+        let (x, y) = undo.pos;
+        let sub = (x / 3) * 3 + y / 3;
+        let cell = (x % 3) * 3 + y % 3;
+
+        self.x[sub] &= !(1 << cell);
+        self.o[sub] &= !(1 << cell);
+        self.update(sub);
+
+        self.rehash(&undo);
+        self.active = undo.active;
+        self.turn = undo.turn;
+    }
+
+    /// Brings the hash from one side of `undo` to the other, in either direction.
+    fn rehash(&mut self, undo: &Undo) {
+        self.hash ^= KEYS.cell(undo.pos, undo.turn);
+        self.hash ^= KEYS.active(undo.active) ^ KEYS.active(self.active);
+        self.hash ^= KEYS.turn(undo.turn) ^ KEYS.turn(self.turn);
+    }
+
+    /// Works out whether sub-board `sub` is won or blocked.
+    fn update(&mut self, sub: usize) {
+        let bit = 1 << sub;
+        self.won_x &= !bit;
+        self.won_o &= !bit;
+        self.blocked &= !bit;
+
+        let info = self.sub_info(sub);
+        match info.winner {
+            Piece::X => self.won_x |= bit,
+            Piece::O => self.won_o |= bit,
+            Piece::None if info.blocked => self.blocked |= bit,
+            Piece::None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_info_matches_the_lines() {
+        for x in 0..512 {
+            for o in (0..512).filter(|o| o & x == 0) {
+                let info = SubInfo::of(x, o);
+                let winner = if has_line(x) {
+                    Piece::X
+                } else if has_line(o) {
+                    Piece::O
+                } else {
+                    Piece::None
+                };
+                assert_eq!(info.winner, winner);
+                assert_eq!(
+                    info.blocked,
+                    winner == Piece::None
+                        && LINES_TOUCHED[x as usize] & LINES_TOUCHED[o as usize] == 0xff,
+                    "{x:09b} {o:09b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sub_info_solves_tic_tac_toe() {
+        // an empty board is a draw, and so is one where O answered a corner in the centre
+        assert_eq!(SubInfo::of(0, 0).value, [0, 0]);
+        assert_eq!(SubInfo::of(1 << 0, 1 << 4).value, [0, 0]);
+        // a corner answered by an edge next to it loses for O
+        assert_eq!(SubInfo::of(1 << 0, 1 << 1).value[0], 1);
+        // when both sides have two in a row, whoever moves first wins
+        assert_eq!(SubInfo::of(0b000_000_011, 0b011_000_000).value, [1, -1]);
+    }
+
+    #[test]
+    fn no_playable_sub_boards_is_a_draw() {
+        // every sub-board is won, but neither side has three in a row
+        let winners = [
+            [Piece::X, Piece::O, Piece::X],
+            [Piece::X, Piece::O, Piece::O],
+            [Piece::O, Piece::X, Piece::X],
+        ];
+        let mut game = [[[[Piece::None; 3]; 3]; 3]; 3];
+        for x1 in 0..3 {
+            for y1 in 0..3 {
+                game[x1][y1][0] = [winners[x1][y1]; 3];
+            }
+        }
+
+        let board = Board::new(&game, Piece::X, None);
+        assert!(board.moves().is_empty());
+        assert_eq!(board.winner(), Piece::None);
+        assert!(board.is_draw() && board.is_over());
+        assert!(crate::game_is_draw(&game));
+
+        // the same, except that X has won the whole board
+        game[1][1][0] = [Piece::X; 3];
+        let board = Board::new(&game, Piece::O, None);
+        assert!(!board.is_draw() && board.is_over());
+        assert!(!crate::game_is_draw(&game));
+    }
+}
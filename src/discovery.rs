@@ -0,0 +1,258 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Piece, Player,
+    auth::Key,
+    net::{self, PORT, PROTOCOL_VERSION, Role, Session},
+    server::HANDSHAKE_TIMEOUT,
+};
+
+/// The only rules tut3 knows about, advertised so that future variants can be told apart.
+pub const RULES_VARIANT: &str = "standard";
+
+const MAGIC: &[u8; 4] = b"TUT3";
+const KIND_QUERY: u8 = b'Q';
+const KIND_ADVERT: u8 = b'G';
+
+const SEARCH_TIME: Duration = Duration::from_millis(1500);
+const QUERY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An open game found on the LAN.
+///
+/// Hosts answer a broadcast `[MAGIC, KIND_QUERY, version]` datagram on UDP `PORT` with
+/// `[MAGIC, KIND_ADVERT, version, free side, locked, TCP port (u16), variant length, variant, name]`.
+#[derive(Clone, Debug)]
+pub struct Advert {
+    pub addr: SocketAddr,
+    pub name: String,
+    pub variant: String,
+    /// The piece the joining player will play.
+    pub free: Piece,
+    /// Whether the game needs a password.
+    pub locked: bool,
+}
+
+impl Advert {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend([
+            KIND_ADVERT,
+            PROTOCOL_VERSION,
+            self.free.as_u8(),
+            self.locked as u8,
+        ]);
+        bytes.extend(self.addr.port().to_be_bytes());
+        bytes.push(self.variant.len() as u8);
+        bytes.extend(self.variant.as_bytes());
+        bytes.extend(self.name.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8], from: IpAddr) -> Option<Advert> {
+        let rest = bytes.strip_prefix(MAGIC)?;
+        let [
+            KIND_ADVERT,
+            PROTOCOL_VERSION,
+            free,
+            locked @ (0 | 1),
+            port_hi,
+            port_lo,
+            variant_len,
+            rest @ ..,
+        ] = rest
+        else {
+            return None;
+        };
+        let variant = rest.get(..*variant_len as usize)?;
+        let name = &rest[*variant_len as usize..];
+
+        Some(Advert {
+            addr: SocketAddr::new(from, u16::from_be_bytes([*port_hi, *port_lo])),
+            name: String::from_utf8_lossy(name).into_owned(),
+            variant: String::from_utf8_lossy(variant).into_owned(),
+            free: Piece::from_u8(*free).filter(|piece| *piece != Piece::None)?,
+            locked: *locked == 1,
+        })
+    }
+}
+
+impl Display for Advert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} rules) at {}, you would play {}",
+            self.name,
+            self.variant,
+            self.addr.ip(),
+            self.free
+        )?;
+        if self.locked {
+            write!(f, ", needs a password")?;
+        }
+        Ok(())
+    }
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+fn query() -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend([KIND_QUERY, PROTOCOL_VERSION]);
+    bytes
+}
+
+/// Answers discovery queries with `advert` until `stop` is set.
+fn advertise(advert: Advert, stop: Arc<AtomicBool>) -> io::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", PORT))?;
+    socket.set_read_timeout(Some(QUERY_INTERVAL))?;
+
+    let reply = advert.to_bytes();
+    let mut buf = [0u8; 64];
+    while !stop.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) if buf[..len] == query()[..] => {
+                socket.send_to(&reply, from)?;
+            }
+            Ok(_) => {}
+            Err(e) if timed_out(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Broadcasts discovery queries for a little while and returns every game that answered.
+pub fn search() -> io::Result<Vec<Advert>> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+    let mut buf = [0u8; 512];
+    let start = Instant::now();
+    let mut last_query = None;
+
+    while start.elapsed() < SEARCH_TIME {
+        if last_query.is_none_or(|at: Instant| at.elapsed() >= QUERY_INTERVAL) {
+            socket.send_to(&query(), (Ipv4Addr::BROADCAST, PORT))?;
+            last_query = Some(Instant::now());
+        }
+
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if let Some(advert) = Advert::from_bytes(&buf[..len], from.ip())
+                    && seen.insert(advert.addr)
+                {
+                    found.push(advert);
+                }
+            }
+            Err(e) if timed_out(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(found)
+}
+
+/// Advertises a game called `name` on the LAN and plays it with `player` against whoever joins
+/// first. `side` is the piece played by the host, and with a `key` only someone who knows the
+/// password can join. A player who loses the connection has `grace` to come back.
+pub fn host(name: String, side: Piece, player: Player, grace: Duration, key: Option<Key>) {
+    let listener = TcpListener::bind(("0.0.0.0", PORT))
+        .unwrap_or_else(|e| panic!("Failed to listen on port {PORT}: {e}"));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let advert = Advert {
+        addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), PORT),
+        name,
+        variant: RULES_VARIANT.to_string(),
+        free: side.other(),
+        locked: key.is_some(),
+    };
+    let advertiser = {
+        let stop = stop.clone();
+        let advert = advert.clone();
+        thread::spawn(move || advertise(advert, stop))
+    };
+
+    println!(
+        "Hosting \"{}\" as {side}, waiting for someone to join...",
+        advert.name
+    );
+    let session = loop {
+        let (stream, from) = listener.accept().expect("Failed to accept connection");
+        // so that a connection that never says hello can't keep the game from being joined
+        let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+        match Session::host(stream, side, key.as_ref()) {
+            Ok(session) => {
+                session.set_timeout(None);
+                break session;
+            }
+            Err(e) => println!("{} could not join: {e}", from.ip()),
+        }
+    };
+
+    stop.store(true, Ordering::Relaxed);
+    if let Ok(Err(e)) = advertiser.join() {
+        println!("Warning: the game could not be advertised ({e})");
+    }
+    // the port is listened on again if the other side has to call back
+    drop(listener);
+
+    net::play(session, player, grace, key);
+}
+
+/// Lists the games on the LAN, lets the player pick one and plays it with `player`. `key` is
+/// needed to join games with a password.
+pub fn join(player: Player, key: Option<Key>) {
+    println!("Looking for games...");
+    let adverts = search().unwrap_or_else(|e| panic!("Failed to search for games: {e}"));
+    if adverts.is_empty() {
+        println!("No games found. Ask someone to run `tut3 host`!");
+        return;
+    }
+
+    for (i, advert) in adverts.iter().enumerate() {
+        println!("{}) {advert}", i + 1);
+    }
+
+    let advert = loop {
+        println!("Which game would you like to join?");
+        let mut input_text = String::new();
+        io::stdin()
+            .read_line(&mut input_text)
+            .expect("Failed to read line");
+
+        match input_text.trim().parse::<usize>() {
+            Ok(i) if (1..=adverts.len()).contains(&i) => break &adverts[i - 1],
+            _ => println!("Please enter a number between 1 and {}", adverts.len()),
+        }
+    };
+
+    if advert.locked && key.is_none() {
+        println!("That game needs a password, use `tut3 join --password <password>`");
+        return;
+    }
+
+    let stream = TcpStream::connect(advert.addr)
+        .unwrap_or_else(|e| panic!("Failed to connect to {}: {e}", advert.addr));
+    let session = Session::join(stream, Role::Peer, advert.free, key.as_ref())
+        .unwrap_or_else(|e| panic!("Failed to join {}: {e}", advert.name));
+    // the host decides how long either side may be away
+    net::play(session, player, Duration::ZERO, key);
+}
@@ -0,0 +1,213 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    Piece,
+    board::{Board, LINES_TOUCHED, Mask, PAIRS},
+    minimax::WIN_SCORE,
+};
+
+const CENTER: Mask = 0b000_010_000;
+const CORNERS: Mask = 0b101_000_101;
+const EDGES: Mask = 0b010_101_010;
+
+/// The number of features the evaluation looks at.
+pub const FEATURES: usize = 7;
+
+/// The name of each feature in a weights file, in the order of `Weights`.
+pub const FEATURE_NAMES: [&str; FEATURES] = [
+    "won_center",
+    "won_corner",
+    "won_edge",
+    "sub_threats",
+    "board_threats",
+    "sub_values",
+    "free_choice",
+];
+
+/// How much the smart bot values each feature of a position. Features count for one side and
+/// against the other, so that a position scores the same for both sides but for the sign:
+/// - `won_center`, `won_corner`, `won_edge`: sub-boards won in the centre, corners and edges
+/// - `sub_threats`: lines of an open sub-board with two pieces and the third cell empty
+/// - `board_threats`: lines of the whole board with two won sub-boards and the third still open
+/// - `sub_values`: open sub-boards that would be won on their own with perfect play
+/// - `free_choice`: 1 if the side to move may play in any sub-board
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Weights(pub [isize; FEATURES]);
+
+impl Default for Weights {
+    /// The weights the smart bot has always played with.
+    fn default() -> Weights {
+        Weights([100, 100, 100, 1, 0, 0, 0])
+    }
+}
+
+impl Weights {
+    /// Scores `board` from `me`'s point of view.
+    pub fn eval(&self, board: &Board, me: Piece) -> isize {
+        let mut points = features(board, me)
+            .iter()
+            .zip(self.0)
+            .map(|(feature, weight)| feature * weight)
+            .sum();
+
+        let game_won = board.winner();
+        if game_won == Piece::None {
+        } else if game_won == me {
+            points += WIN_SCORE;
+        } else {
+            points -= WIN_SCORE;
+        }
+
+        points
+    }
+}
+
+/// The features of `board` from `me`'s point of view, in the order of `FEATURE_NAMES`.
+pub fn features(board: &Board, me: Piece) -> [isize; FEATURES] {
+    let (won_me, won_them) = (board.won(me), board.won(me.other()));
+    let count = |mask: Mask| mask.count_ones() as isize;
+    let balance = |mask: Mask| count(won_me & mask) - count(won_them & mask);
+
+    let sign = if me == Piece::O { -1 } else { 1 };
+    let (mut threats, mut values) = (0, 0);
+    let value = if board.turn() == Piece::O { 1 } else { 0 };
+    for sub in 0..9 {
+        if (won_me | won_them | board.blocked()) & (1 << sub) == 0 {
+            let info = board.sub_info(sub);
+            threats += info.threat_balance(me);
+            // the values are from X's point of view, with the side to move moving first
+            values += info.value[value] as isize * sign;
+        }
+    }
+
+    // lines of the whole board that need one more sub-board, and nobody has got in the way of
+    let board_threats = |mine: Mask, theirs: Mask| {
+        (PAIRS[mine as usize] & !LINES_TOUCHED[(theirs | board.blocked()) as usize]).count_ones()
+            as isize
+    };
+
+    [
+        balance(CENTER),
+        balance(CORNERS),
+        balance(EDGES),
+        threats,
+        board_threats(won_me, won_them) - board_threats(won_them, won_me),
+        values,
+        board.active().is_none() as isize * if board.turn() == me { 1 } else { -1 },
+    ]
+}
+
+/// Why a weights file could not be read. Lines are numbered from 1.
+#[derive(Debug, PartialEq, Clone)]
+pub enum WeightsError {
+    /// A line isn't a feature name followed by a number.
+    BadLine(usize),
+    UnknownFeature(usize, String),
+}
+
+impl Display for WeightsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WeightsError::BadLine(line) => {
+                write!(f, "line {line} should be a feature name and a whole number")
+            }
+            WeightsError::UnknownFeature(line, name) => {
+                write!(f, "line {line} has the unknown feature {name}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WeightsError {}
+
+/// Reads a weights file: one feature name and its weight per line, with `#` starting a comment.
+/// Features that aren't mentioned keep their default weight.
+impl FromStr for Weights {
+    type Err = WeightsError;
+
+    fn from_str(text: &str) -> Result<Weights, WeightsError> {
+        let mut weights = Weights::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let (Some(name), Some(weight), None) = (words.next(), words.next(), words.next())
+            else {
+                return Err(WeightsError::BadLine(i + 1));
+            };
+            let weight = weight.parse().map_err(|_| WeightsError::BadLine(i + 1))?;
+            let feature = FEATURE_NAMES
+                .iter()
+                .position(|feature| *feature == name)
+                .ok_or_else(|| WeightsError::UnknownFeature(i + 1, name.to_string()))?;
+            weights.0[feature] = weight;
+        }
+
+        Ok(weights)
+    }
+}
+
+/// Writes the weights in the form `from_str` reads.
+impl Display for Weights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, weight) in FEATURE_NAMES.iter().zip(self.0) {
+            writeln!(f, "{name} {weight}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_is_the_same_for_both_sides() {
+        let (x, o, none) = (Piece::X, Piece::O, Piece::None);
+        let mut game = [[[[none; 3]; 3]; 3]; 3];
+        game[0][0][0] = [x; 3];
+        game[1][2][1] = [o; 3];
+        game[1][0] = [[x, o, x], [x, o, o], [o, x, x]];
+        game[1][1][0] = [x, x, none];
+        game[2][1][2] = [o, none, o];
+        game[0][2][1] = [x, x, none];
+
+        let board = Board::new(&game, x, None);
+        assert_ne!(board.blocked(), 0);
+        let weights = Weights::default();
+        assert_eq!(weights.eval(&board, x), -weights.eval(&board, o));
+        assert_eq!(
+            features(&board, x),
+            features(&board, o).map(|feature| -feature)
+        );
+    }
+
+    #[test]
+    fn weights_round_trip() {
+        let weights = Weights([1, -2, 3, -4, 5, -6, 7]);
+        assert_eq!(weights.to_string().parse(), Ok(weights));
+    }
+
+    #[test]
+    fn weights_file_keeps_defaults_and_reports_errors() {
+        let mut expected = Weights::default();
+        expected.0[4] = 40;
+        assert_eq!(
+            "# tuned\n\nboard_threats 40 # up from 0\n".parse(),
+            Ok(expected)
+        );
+
+        assert_eq!(
+            "won_center 100\nwon_edge".parse::<Weights>(),
+            Err(WeightsError::BadLine(2))
+        );
+        assert_eq!(
+            "center 100".parse::<Weights>(),
+            Err(WeightsError::UnknownFeature(1, "center".to_string()))
+        );
+    }
+}
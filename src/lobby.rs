@@ -0,0 +1,645 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Display,
+    io,
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Human, Piece, Player,
+    auth::Key,
+    net::{MAX_MESSAGE_LEN, MAX_NAME_LEN, Message, NetError, Role, Session, resolve},
+    server::{self, Games, HANDSHAKE_TIMEOUT, RECONNECT_INTERVAL, RejoinCode},
+};
+
+/// How many times a player back from a game tries to get into the lobby again.
+const RETURN_ATTEMPTS: u32 = 5;
+/// How long a challenge stays open.
+const INVITE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the lobby checks for challenges that nobody answered.
+const TICK: Duration = Duration::from_secs(1);
+
+/// What someone in the list of players is up to.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Status {
+    /// Not connected, but on the ladder from earlier games.
+    Away,
+    Idle,
+    Seeking,
+    Playing,
+}
+
+impl Status {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Status::Away => 0,
+            Status::Idle => 1,
+            Status::Seeking => 2,
+            Status::Playing => 3,
+        }
+    }
+
+    pub fn from_u8(u8: u8) -> Option<Status> {
+        match u8 {
+            0 => Some(Status::Away),
+            1 => Some(Status::Idle),
+            2 => Some(Status::Seeking),
+            3 => Some(Status::Playing),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Status::Away => "away",
+            Status::Idle => "in the lobby",
+            Status::Seeking => "looking for a game",
+            Status::Playing => "playing",
+        };
+        f.pad(status)
+    }
+}
+
+/// One line of the lobby's list of players, with their results on the ladder.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Listing {
+    pub name: String,
+    pub status: Status,
+    pub wins: u16,
+    pub losses: u16,
+    pub draws: u16,
+}
+
+impl Listing {
+    /// How many bytes the listing takes up in a `Players` message: the name and its length, the
+    /// status and the three results.
+    fn encoded_len(&self) -> usize {
+        self.name.len() + 8
+    }
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<20}  {:<18}  {} won, {} lost, {} drawn",
+            self.name, self.status, self.wins, self.losses, self.draws
+        )
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Record {
+    wins: u16,
+    losses: u16,
+    draws: u16,
+}
+
+/// Who a player in the lobby would like to play.
+#[derive(PartialEq)]
+enum Want {
+    Anyone,
+    Opponent(String),
+}
+
+/// Someone in the lobby, waiting to be put in a game.
+struct Member {
+    name: String,
+    session: Session,
+    connection: u64,
+    wants: Option<Want>,
+    invited_at: Option<Instant>,
+}
+
+impl Member {
+    fn would_play(&self, other: &Member) -> bool {
+        match &self.wants {
+            Some(Want::Anyone) => true,
+            Some(Want::Opponent(name)) => *name == other.name,
+            None => false,
+        }
+    }
+
+    fn notice(&mut self, text: String) {
+        let _ = self.session.send(&Message::Notice(text));
+    }
+
+    /// Turns down what the player asked for, so that they can ask for something else.
+    fn refuse(&mut self, reason: String) {
+        self.wants = None;
+        self.invited_at = None;
+        let _ = self.session.send(&Message::Illegal(reason));
+    }
+}
+
+/// Something that happened in the lobby. Connections are numbered like those of a refereed game.
+enum Event {
+    Entered(Session, String),
+    Message(u64, Message),
+    Left(u64),
+    /// A game between the two named players, X first, ended with this winner.
+    Finished([String; 2], Piece),
+}
+
+/// Matches up the players in the lobby and keeps the ladder.
+struct Lobby {
+    members: Vec<Member>,
+    /// The players in a game started by the lobby.
+    playing: Vec<String>,
+    records: BTreeMap<String, Record>,
+    connections: u64,
+    events: Sender<Event>,
+    games: Games,
+    grace: Duration,
+}
+
+impl Lobby {
+    fn run(&mut self, events: Receiver<Event>) {
+        loop {
+            let event = match events.recv_timeout(TICK) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.expire();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            match event {
+                Event::Entered(session, name) => self.enter(session, name),
+                Event::Message(connection, message) => {
+                    if let Some(i) = self.find(connection) {
+                        self.handle(i, message);
+                    }
+                }
+                Event::Left(connection) => {
+                    if let Some(i) = self.find(connection) {
+                        let member = self.members.remove(i);
+                        member.session.close();
+                        println!("{} left the lobby", member.name);
+                    }
+                }
+                Event::Finished(names, winner) => self.finish(names, winner),
+            }
+            self.list();
+        }
+    }
+
+    fn find(&self, connection: u64) -> Option<usize> {
+        self.members
+            .iter()
+            .position(|member| member.connection == connection)
+    }
+
+    fn enter(&mut self, mut session: Session, name: String) {
+        let name = name.trim().to_string();
+        if name.is_empty()
+            || name.chars().count() > MAX_NAME_LEN
+            || name.chars().any(char::is_control)
+        {
+            let _ = session.reject(&format!(
+                "names must be 1 to {MAX_NAME_LEN} characters long"
+            ));
+            return;
+        }
+        if self.members.iter().any(|member| member.name == name) {
+            let _ = session.reject(&format!("someone called {name} is already in the lobby"));
+            return;
+        }
+        if self.playing.contains(&name) {
+            let _ = session.reject(&format!("someone called {name} is playing a game"));
+            return;
+        }
+        if session
+            .send(&Message::Welcome { side: Piece::None })
+            .is_err()
+        {
+            return;
+        }
+        session.set_timeout(None);
+
+        self.connections += 1;
+        let connection = self.connections;
+        server::forward(
+            &session,
+            self.events.clone(),
+            move |message| Event::Message(connection, message),
+            Event::Left(connection),
+        );
+
+        println!("{name} entered the lobby");
+        self.members.push(Member {
+            name,
+            session,
+            connection,
+            wants: None,
+            invited_at: None,
+        });
+    }
+
+    fn expire(&mut self) {
+        let mut expired = false;
+        for member in &mut self.members {
+            if let Some(Want::Opponent(name)) = &member.wants
+                && member
+                    .invited_at
+                    .is_some_and(|at| at.elapsed() >= INVITE_TIMEOUT)
+            {
+                member.refuse(format!("{name} did not accept your challenge."));
+                expired = true;
+            }
+        }
+        if expired {
+            self.list();
+        }
+    }
+
+    fn handle(&mut self, i: usize, message: Message) {
+        match message {
+            Message::Seek => {
+                self.members[i].wants = Some(Want::Anyone);
+                self.members[i].notice("Looking for an opponent...".into());
+            }
+            Message::Invite { name } => {
+                let from = self.members[i].name.clone();
+                if name == from {
+                    self.members[i].refuse("You can't challenge yourself!".into());
+                    return;
+                }
+                let Some(j) = self.members.iter().position(|member| member.name == name) else {
+                    self.members[i].refuse(format!("Nobody called {name} is in the lobby."));
+                    return;
+                };
+
+                if !self.members[j].would_play(&self.members[i]) {
+                    self.members[j].notice(format!(
+                        "{from} challenges you! Type `challenge {from}` to accept."
+                    ));
+                }
+                self.members[i].wants = Some(Want::Opponent(name.clone()));
+                self.members[i].invited_at = Some(Instant::now());
+                self.members[i].notice(format!("Waiting for {name} to accept..."));
+            }
+            Message::Bye => {
+                let member = self.members.remove(i);
+                member.session.close();
+                println!("{} left the lobby", member.name);
+                return;
+            }
+            _ => return,
+        }
+
+        if let Some(j) = (0..self.members.len()).find(|j| {
+            *j != i
+                && self.members[i].would_play(&self.members[*j])
+                && self.members[*j].would_play(&self.members[i])
+        }) {
+            self.start(i, j);
+        }
+    }
+
+    /// Starts a game between two members, who leave the lobby and join it with the rejoin codes
+    /// they are sent.
+    fn start(&mut self, i: usize, j: usize) {
+        let (first, second) = if i > j { (i, j) } else { (j, i) };
+        let mut players = [self.members.remove(first), self.members.remove(second)];
+        if rand::random() {
+            players.swap(0, 1);
+        }
+
+        let (id, tokens, referee) = server::start_game(&self.games, Vec::new(), self.grace);
+        let names = players.each_ref().map(|player| player.name.clone());
+        println!("Game {id}: {} against {}", names[0], names[1]);
+
+        for (k, (player, side)) in players.iter_mut().zip([Piece::X, Piece::O]).enumerate() {
+            player.notice(format!(
+                "You are playing {} as {side} in game {id}.",
+                names[1 - k]
+            ));
+            let _ = player.session.send(&Message::Joined {
+                game: id,
+                token: tokens[k],
+                grace: self.grace.as_secs() as u16,
+            });
+            player.session.close();
+        }
+
+        self.playing.extend(names.iter().cloned());
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let winner = referee.join().unwrap_or(Piece::None);
+            let _ = events.send(Event::Finished(names, winner));
+        });
+    }
+
+    fn finish(&mut self, names: [String; 2], winner: Piece) {
+        self.playing.retain(|name| !names.contains(name));
+
+        for (name, side) in names.iter().zip([Piece::X, Piece::O]) {
+            let record = self.records.entry(name.clone()).or_default();
+            match winner {
+                Piece::None => record.draws += 1,
+                winner if winner == side => record.wins += 1,
+                _ => record.losses += 1,
+            }
+        }
+
+        match winner {
+            Piece::None => println!("{} and {} drew", names[0], names[1]),
+            Piece::X => println!("{} beat {}", names[0], names[1]),
+            Piece::O => println!("{} beat {}", names[1], names[0]),
+        }
+    }
+
+    /// Sends everyone in the lobby the list of players, those in the lobby first, then the best
+    /// players on the ladder.
+    fn list(&mut self) {
+        let record = |name: &String| self.records.get(name).copied().unwrap_or_default();
+        let mut players: Vec<Listing> = Vec::new();
+
+        let members = self.members.iter().map(|member| {
+            let status = if member.wants.is_some() {
+                Status::Seeking
+            } else {
+                Status::Idle
+            };
+            (&member.name, status)
+        });
+        let playing = self.playing.iter().map(|name| (name, Status::Playing));
+        let away = self.records.keys().map(|name| (name, Status::Away));
+
+        for (name, status) in members.chain(playing).chain(away) {
+            if players.iter().any(|player| player.name == *name) {
+                continue;
+            }
+            let Record {
+                wins,
+                losses,
+                draws,
+            } = record(name);
+            players.push(Listing {
+                name: name.clone(),
+                status,
+                wins,
+                losses,
+                draws,
+            });
+        }
+
+        players[self.members.len()..].sort_by_key(|player| {
+            (
+                player.status == Status::Away,
+                Reverse(player.wins),
+                player.losses,
+            )
+        });
+        // the list has to fit in one message, and names can take up to four bytes a character
+        let mut len = 1;
+        let fits = players
+            .iter()
+            .take_while(|player| {
+                len += player.encoded_len();
+                len <= MAX_MESSAGE_LEN
+            })
+            .count();
+        players.truncate(fits);
+
+        let message = Message::Players(players);
+        for member in &mut self.members {
+            let _ = member.session.send(&message);
+        }
+    }
+}
+
+/// Runs a lobby that players can enter with a name, see who else is there and either play the
+/// next person who wants a game or challenge someone by name. Games are refereed like on a
+/// server, and the lobby keeps a ladder of everyone's results.
+pub fn serve(port: u16, grace: Duration, key: Option<Key>) {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .unwrap_or_else(|e| panic!("Failed to listen on port {port}: {e}"));
+    println!("Lobby open on port {port}");
+
+    let games = Games::default();
+    let (events, received) = mpsc::channel();
+    {
+        let mut lobby = Lobby {
+            members: Vec::new(),
+            playing: Vec::new(),
+            records: BTreeMap::new(),
+            connections: 0,
+            events: events.clone(),
+            games: games.clone(),
+            grace,
+        };
+        thread::spawn(move || lobby.run(received));
+    }
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (events, games, key) = (events.clone(), games.clone(), key.clone());
+        // a connection that never says hello only holds up its own thread
+        thread::spawn(move || admit(stream, &events, &games, key.as_ref()));
+    }
+}
+
+/// Shakes hands with a new connection to the lobby and hands it over as a member entering the
+/// lobby, a player rejoining a game or a spectator.
+fn admit(stream: TcpStream, events: &Sender<Event>, games: &Games, key: Option<&Key>) {
+    let Ok(from) = stream.peer_addr() else {
+        return;
+    };
+
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let (mut session, role, _) = match Session::accept(stream, key) {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            println!("{from} could not connect: {e}");
+            return;
+        }
+    };
+
+    match role {
+        Role::Lobby => match session.recv() {
+            Ok(Message::Enter { name }) => {
+                let _ = events.send(Event::Entered(session, name));
+            }
+            _ => {
+                let _ = session.reject("expected a name");
+            }
+        },
+        Role::Spectator => server::add_spectator(session, games),
+        Role::Returning => server::rejoin(session, from, games),
+        Role::Player | Role::Peer => {
+            let _ = session.reject("this is a tut3 lobby, use `tut3 lobby <address>`");
+        }
+    }
+}
+
+fn print_players(players: &[Listing]) {
+    println!("Players:");
+    for player in players {
+        println!("  {player}");
+    }
+}
+
+/// What a lobby player waits for after asking for a game.
+enum Answer {
+    Game(RejoinCode),
+    Refused,
+    Lost,
+}
+
+/// Reads a lobby command from the person at the keyboard, or `None` if they want to leave.
+fn read_request(players: &Mutex<Vec<Listing>>) -> Option<Message> {
+    println!("Type `play` to play the next person who wants a game, `challenge <name>`,");
+    println!("`list` to see who is here again, or `quit` to leave.");
+    loop {
+        let mut input_text = String::new();
+        let read = io::stdin()
+            .read_line(&mut input_text)
+            .expect("Failed to read line");
+        if read == 0 {
+            return None;
+        }
+
+        let input_text = input_text.trim();
+        match input_text.split_once(' ') {
+            _ if input_text.eq_ignore_ascii_case("play") => return Some(Message::Seek),
+            Some((command, name)) if command.eq_ignore_ascii_case("challenge") => {
+                let name = name.trim();
+                if name.chars().count() > MAX_NAME_LEN {
+                    println!("Names are at most {MAX_NAME_LEN} characters long");
+                    continue;
+                }
+                return Some(Message::Invite {
+                    name: name.to_string(),
+                });
+            }
+            _ if input_text.eq_ignore_ascii_case("list") => {
+                print_players(&players.lock().unwrap());
+            }
+            _ if input_text.eq_ignore_ascii_case("quit") => return None,
+            _ => println!("Please type `play`, `challenge <name>`, `list` or `quit`"),
+        }
+    }
+}
+
+/// Waits in the lobby until a game is found, returning the code to join it with. A human picks
+/// what to do, anything else asks for a game against anyone.
+fn wait_for_game(mut session: Session, human: bool) -> Option<RejoinCode> {
+    let players = Arc::new(Mutex::new(Vec::new()));
+    let (answers, answer) = mpsc::channel();
+
+    let mut reader = session.try_clone().ok()?;
+    let shared = players.clone();
+    thread::spawn(move || {
+        loop {
+            let message = match reader.recv() {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("Lost connection to the lobby: {e}");
+                    let _ = answers.send(Answer::Lost);
+                    return;
+                }
+            };
+
+            match message {
+                Message::Players(players) => {
+                    if human {
+                        print_players(&players);
+                    }
+                    *shared.lock().unwrap() = players;
+                }
+                Message::Notice(text) => println!("{text}"),
+                Message::Illegal(reason) => {
+                    println!("{reason}");
+                    let _ = answers.send(Answer::Refused);
+                }
+                Message::Joined { game, token, .. } => {
+                    let _ = answers.send(Answer::Game(RejoinCode { game, token }));
+                    return;
+                }
+                _ => {}
+            }
+        }
+    });
+
+    loop {
+        let request = if human {
+            let Some(request) = read_request(&players) else {
+                let _ = session.send(&Message::Bye);
+                return None;
+            };
+            request
+        } else {
+            Message::Seek
+        };
+
+        session.send(&request).ok()?;
+        if human {
+            println!("Press Ctrl-C to leave the lobby.");
+        }
+
+        match answer.recv() {
+            Ok(Answer::Game(code)) => return Some(code),
+            Ok(Answer::Refused) if human => {}
+            Ok(Answer::Refused) | Ok(Answer::Lost) | Err(_) => return None,
+        }
+    }
+}
+
+/// Enters the lobby at `addr` as `name` and plays the games found there with `player`, going back
+/// to the lobby after each one. Without a `player`, the person at the keyboard picks opponents
+/// and plays.
+pub fn enter(addr: &str, name: String, player: Option<Player>, key: Option<Key>) {
+    let lobby = resolve(addr).unwrap_or_else(|| panic!("Could not find lobby {addr}"));
+    let human = player.is_none();
+    let player = player.unwrap_or_else(|| Arc::new(Human));
+    if name.trim().chars().count() > MAX_NAME_LEN {
+        panic!("Names must be at most {MAX_NAME_LEN} characters long");
+    }
+
+    let mut back_from_game = false;
+    loop {
+        let mut attempts = 0;
+        let session = loop {
+            let stream = TcpStream::connect(lobby)
+                .unwrap_or_else(|e| panic!("Failed to connect to {lobby}: {e}"));
+            match Session::join_with(stream, Role::Lobby, Piece::None, key.as_ref(), |session| {
+                session.send(&Message::Enter { name: name.clone() })
+            }) {
+                Ok(session) => break session,
+                // the lobby keeps the name taken until it hears that the last game is over
+                Err(NetError::Rejected(_)) if back_from_game && attempts < RETURN_ATTEMPTS => {
+                    attempts += 1;
+                    thread::sleep(RECONNECT_INTERVAL);
+                }
+                Err(e) => panic!("Failed to enter the lobby at {lobby}: {e}"),
+            }
+        };
+        println!("Entered the lobby as {name}.");
+
+        let Some(code) = wait_for_game(session, human) else {
+            return;
+        };
+        server::connect(
+            &lobby.to_string(),
+            Piece::None,
+            Some(code),
+            player.clone(),
+            key.clone(),
+        );
+        back_from_game = true;
+    }
+}
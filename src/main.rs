@@ -1,16 +1,39 @@
 #![allow(dead_code)]
+mod analyze;
+mod arena;
+mod auth;
+mod board;
+mod discovery;
+mod eval;
+mod lobby;
+mod mcts;
+mod minimax;
+mod net;
+mod policy;
+mod server;
+mod spectate;
+mod tune;
+
 use std::{
     env,
     fmt::Display,
+    fs,
     io::{self, Cursor, Read},
-    isize,
-    net::{IpAddr, UdpSocket},
-    rc::Rc,
+    net::IpAddr,
+    path::Path,
     str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
+use board::{Board, SubInfo};
+use eval::Weights;
+use minimax::{Budget, Level, Minimax};
+use policy::Policy;
+
 const RESET: &str = "\x1b[0m";
 const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
 const BLUE: &str = "\x1b[34m";
 const COLOR_O: &str = RED;
 const COLOR_X: &str = BLUE;
@@ -28,7 +51,7 @@ const WINS: [[(usize, usize); 3]; 8] = [
     [(0, 2), (1, 1), (2, 0)],
 ];
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 enum Piece {
     None,
     X,
@@ -52,12 +75,12 @@ impl Piece {
         }
     }
 
-    fn from_u8(u8: u8) -> Piece {
+    fn from_u8(u8: u8) -> Option<Piece> {
         match u8 {
-            0 => Piece::None,
-            1 => Piece::X,
-            2 => Piece::O,
-            _ => panic!(),
+            0 => Some(Piece::None),
+            1 => Some(Piece::X),
+            2 => Some(Piece::O),
+            _ => None,
         }
     }
 }
@@ -83,81 +106,223 @@ impl Display for Piece {
 
 type Subgame = [[Piece; 3]; 3];
 type Game = [[Subgame; 3]; 3];
-type Player = Rc<dyn PlayerTrait>;
+type Player = Arc<dyn PlayerTrait>;
+
+const STATE_MAGIC: [u8; 4] = *b"TUT3";
+const STATE_VERSION: u8 = 1;
+const STATE_MAX_LEN: usize = 4 + 1 + 3 + 1 + 81 + 1 + 4;
+
+/// Why a snapshot passed to `GameState::update_from_bytes` could not be restored.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum StateError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    ChecksumMismatch,
+    InvalidActive,
+    FinishedActive,
+    MissingTurn,
+    InvalidPiece(u8),
+    WrongMoveNumber(u8),
+    TrailingBytes,
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::TooShort => write!(f, "the game state is incomplete"),
+            StateError::BadMagic => write!(f, "the data is not a tut3 game state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "game state version {version} is not supported")
+            }
+            StateError::ChecksumMismatch => write!(f, "the game state is corrupted"),
+            StateError::InvalidActive => write!(f, "the active game is out of bounds"),
+            StateError::FinishedActive => write!(f, "the active game is already finished"),
+            StateError::MissingTurn => write!(f, "nobody is to move in an unfinished game"),
+            StateError::InvalidPiece(piece) => write!(f, "{piece} is not a valid piece"),
+            StateError::WrongMoveNumber(number) => write!(
+                f,
+                "move number {number} does not match the number of pieces on the board"
+            ),
+            StateError::TrailingBytes => write!(f, "the game state has unexpected trailing data"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
 
 #[derive(Clone)]
 struct GameState {
     active: Option<(usize, usize)>,
     message: Option<String>,
+    /// The latest chat lines, shown above the board.
+    chat: Vec<String>,
     game: Game,
     turn: Piece,
+    resigned: Piece,
     player_1: Player,
     player_2: Player,
 }
 
 impl GameState {
+    /// Encodes the board, active game and turn as a snapshot that `update_from_bytes` can restore.
+    ///
+    /// The layout (version 1) is:
+    /// - `b"TUT3"` followed by the format version
+    /// - the active game, either `[0]` or `[1, x, y]`
+    /// - the piece whose turn it is
+    /// - all 81 cells, ordered by `x1`, `y1`, `x0`, `y0`
+    /// - the move number, which is the number of pieces on the board
+    /// - a big-endian CRC-32 of everything before it
     fn update_to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+        let mut bytes = Vec::with_capacity(STATE_MAX_LEN);
+        bytes.extend(STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+
         match self.active {
-            Some((x, y)) => bytes.extend([2, x as u8, y as u8]),
+            Some((x, y)) => bytes.extend([1, x as u8, y as u8]),
             None => bytes.push(0),
         };
         bytes.push(self.turn.as_u8());
 
-        let mut positions = Vec::new();
         for x1 in 0..3 {
             for y1 in 0..3 {
                 for x0 in 0..3 {
                     for y0 in 0..3 {
-                        positions.push(self.game[x1][y1][x0][y0].as_u8());
+                        bytes.push(self.game[x1][y1][x0][y0].as_u8());
                     }
                 }
             }
         }
 
+        bytes.push(pieces_placed(&self.game));
+        bytes.extend(crc32(&bytes).to_be_bytes());
+
         bytes
     }
 
-    fn update_from_bytes(&mut self, bytes: &[u8]) {
-        let mut cursor = Cursor::new(bytes);
+    /// Restores a snapshot made by `update_to_bytes`. Nothing is changed if the snapshot is invalid.
+    #[allow(clippy::needless_range_loop)]
+    fn update_from_bytes(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        if bytes.len() < 4 {
+            return Err(StateError::TooShort);
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        let mut cursor = Cursor::new(body);
+        let mut buf = [0u8; 4];
+
+        cursor
+            .read_exact(&mut buf)
+            .map_err(|_| StateError::TooShort)?;
+        if buf != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
         let mut buf = [0u8; 1];
-        cursor.read_exact(&mut buf).unwrap();
-        if buf[0] > 0 {
-            let mut buf = [0u8; 2];
-            cursor.read_exact(&mut buf).unwrap();
-            self.active = Some((buf[0] as usize, buf[1] as usize));
-        } else {
-            self.active = None;
+        cursor
+            .read_exact(&mut buf)
+            .map_err(|_| StateError::TooShort)?;
+        if buf[0] != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(buf[0]));
         }
 
-        cursor.read_exact(&mut buf).unwrap();
-        self.turn = Piece::from_u8(buf[0]);
+        if u32::from_be_bytes(checksum.try_into().unwrap()) != crc32(body) {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        cursor
+            .read_exact(&mut buf)
+            .map_err(|_| StateError::TooShort)?;
+        let active = match buf[0] {
+            0 => None,
+            1 => {
+                let mut buf = [0u8; 2];
+                cursor
+                    .read_exact(&mut buf)
+                    .map_err(|_| StateError::TooShort)?;
+                if buf[0] >= 3 || buf[1] >= 3 {
+                    return Err(StateError::InvalidActive);
+                }
+                Some((buf[0] as usize, buf[1] as usize))
+            }
+            _ => return Err(StateError::InvalidActive),
+        };
 
+        cursor
+            .read_exact(&mut buf)
+            .map_err(|_| StateError::TooShort)?;
+        let turn = Piece::from_u8(buf[0]).ok_or(StateError::InvalidPiece(buf[0]))?;
+
+        let mut game = new_game();
         for x1 in 0..3 {
             for y1 in 0..3 {
                 for x0 in 0..3 {
                     for y0 in 0..3 {
-                        cursor.read_exact(&mut buf).unwrap();
-                        self.game[x1][y1][x0][y0] = Piece::from_u8(buf[0]);
+                        cursor
+                            .read_exact(&mut buf)
+                            .map_err(|_| StateError::TooShort)?;
+                        game[x1][y1][x0][y0] =
+                            Piece::from_u8(buf[0]).ok_or(StateError::InvalidPiece(buf[0]))?;
                     }
                 }
             }
         }
+
+        cursor
+            .read_exact(&mut buf)
+            .map_err(|_| StateError::TooShort)?;
+        if buf[0] != pieces_placed(&game) {
+            return Err(StateError::WrongMoveNumber(buf[0]));
+        }
+
+        if cursor.position() as usize != body.len() {
+            return Err(StateError::TrailingBytes);
+        }
+
+        if let Some((x, y)) = active
+            && (subgame_won(&game[x][y]) != Piece::None || subgame_is_draw(&game[x][y]))
+        {
+            return Err(StateError::FinishedActive);
+        }
+        if turn == Piece::None && game_won(&game) == Piece::None && !game_is_draw(&game) {
+            return Err(StateError::MissingTurn);
+        }
+
+        self.active = active;
+        self.turn = turn;
+        self.game = game;
+        Ok(())
     }
 
     fn new(player_1: Player, player_2: Player) -> Self {
         Self {
             active: None,
             message: None,
+            chat: Vec::new(),
             game: new_game(),
             turn: Piece::X,
+            resigned: Piece::None,
             player_1,
             player_2,
         }
     }
 
     fn print(&self) {
-        print_game(&self.game, &self.active);
+        println!("{}", "\n".repeat(100));
+        for line in &self.chat {
+            println!("{line}");
+        }
+        print_game(&self.game, &self.active, None);
+    }
+
+    /// Remembers something `from` said, forgetting the oldest line once there are too many.
+    fn add_chat(&mut self, from: Piece, text: &str) -> &str {
+        if self.chat.len() == CHAT_LINES {
+            self.chat.remove(0);
+        }
+        self.chat.push(format!("{from}: {text}"));
+        self.chat.last().unwrap()
     }
 
     fn manual_turn(&mut self, x: usize, y: usize) -> bool {
@@ -165,33 +330,16 @@ impl GameState {
             self.turn = Piece::X
         }
 
+        if check_move(&self.game, &self.active, (x, y)).is_err() {
+            return false;
+        }
+
         let x0 = x % 3;
         let y0 = y % 3;
         let x1 = x / 3;
         let y1 = y / 3;
 
-        let won = subgame_won(&self.game[x1][y1]);
-        if won != Piece::None {
-            return false;
-        }
-
-        if subgame_is_draw(&self.game[x1][y1]) {
-            return false;
-        }
-
-        if self.active.is_some() {
-            let (ax, ay) = self.active.unwrap();
-            if x1 != ax || y1 != ay {
-                return false;
-            }
-        }
-
-        let piece = self.game[x1][y1][x0][y0];
-        if piece != Piece::None {
-            return false;
-        }
-
-        self.game[x1][y1][x0][y0] = self.turn.clone();
+        self.game[x1][y1][x0][y0] = self.turn;
 
         let won = subgame_won(&self.game[x0][y0]);
         if won == Piece::None && !subgame_is_draw(&self.game[x0][y0]) {
@@ -210,92 +358,52 @@ impl GameState {
         } else {
             self.turn = Piece::X;
         }
-        return true;
+        true
     }
 
     fn turn(&mut self) -> Option<(usize, usize)> {
         loop {
-            if self.message.is_some() {
-                println!("{}", self.message.as_ref().unwrap());
-                self.message = None;
+            if let Some(message) = self.message.take() {
+                println!("{message}");
             }
 
             if self.turn == Piece::None {
                 self.turn = Piece::X
             }
 
-            let pos = if self.turn == Piece::X {
+            let action = if self.turn == Piece::X {
                 self.player_1.play(&self.game, &self.turn, self.active)
             } else {
                 self.player_2.play(&self.game, &self.turn, self.active)
             };
 
-            if pos.is_none() {
-                self.message = Some(format!(
-                    "Invalid move! That position is not within the game boundaries!"
-                ));
-                continue;
-            }
-
-            let (x, y) = pos.unwrap();
-            let x0 = x % 3;
-            let y0 = y % 3;
-            let x1 = x / 3;
-            let y1 = y / 3;
-
-            let won = subgame_won(&self.game[x1][y1]);
-            if won != Piece::None {
-                self.message = Some(format!(
-                    "Invalid move! That position is within a game that has already been won!"
-                ));
-                continue;
-            }
-
-            if subgame_is_draw(&self.game[x1][y1]) {
-                self.message = Some(format!(
-                    "Invalid move! That position is within a game that has already been drawn!"
-                ));
-                continue;
-            }
-
-            if self.active.is_some() {
-                let (ax, ay) = self.active.unwrap();
-                if x1 != ax || y1 != ay {
-                    self.message = Some(format!(
-                        "Invalid move! That position is not within the current active game!"
-                    ));
+            let pos = match action {
+                Some(Action::Move(pos)) => pos,
+                Some(Action::Resign) => {
+                    self.resigned = self.turn;
+                    self.active = None;
+                    return None;
+                }
+                Some(Action::Say(text)) => {
+                    println!("{}", self.add_chat(self.turn, &text));
                     continue;
                 }
-            }
+                None => {
+                    self.message = Some(
+                        "Invalid move! That position is not within the game boundaries!"
+                            .to_string(),
+                    );
+                    continue;
+                }
+            };
 
-            let piece = self.game[x1][y1][x0][y0];
-            if piece != Piece::None {
-                self.message = Some(format!(
-                    "Invalid move! There is already a piece at that position!"
-                ));
+            if let Err(reason) = check_move(&self.game, &self.active, pos) {
+                self.message = Some(format!("Invalid move! {reason}"));
                 continue;
             }
 
-            self.game[x1][y1][x0][y0] = self.turn.clone();
-
-            let won = subgame_won(&self.game[x0][y0]);
-            if won == Piece::None && !subgame_is_draw(&self.game[x0][y0]) {
-                self.active = Some((x0, y0));
-            } else {
-                self.active = None;
-            }
-
-            if self.is_complete() {
-                self.active = None;
-                return pos;
-            }
-
-            if self.turn == Piece::X {
-                self.turn = Piece::O;
-            } else {
-                self.turn = Piece::X;
-            }
-            return pos;
+            self.manual_turn(pos.0, pos.1);
+            return Some(pos);
         }
     }
 
@@ -304,284 +412,142 @@ impl GameState {
     }
 
     fn is_draw(&self) -> bool {
-        game_is_draw(&self.game)
+        self.resigned == Piece::None && game_is_draw(&self.game)
     }
 
     fn won(&self) -> Piece {
+        if self.resigned != Piece::None {
+            return self.resigned.other();
+        }
         game_won(&self.game)
     }
 }
 
-trait PlayerTrait {
-    fn play(
-        &self,
-        game: &Game,
-        turn: &Piece,
-        active: Option<(usize, usize)>,
-    ) -> Option<(usize, usize)>;
+/// What a player decided to do on their turn.
+#[derive(PartialEq, Clone)]
+enum Action {
+    Move((usize, usize)),
+    Resign,
+    /// A chat message, after which the player is asked for their move again.
+    Say(String),
 }
 
-#[derive(Clone)]
-struct Human;
-impl PlayerTrait for Human {
-    fn play(
-        &self,
-        _game: &Game,
-        turn: &Piece,
-        active: Option<(usize, usize)>,
-    ) -> Option<(usize, usize)> {
-        println!(
-            "It's {}'s turn! You can move in any open square between {} and {}",
-            turn,
-            pos_as_string(&move_min(&active)),
-            pos_as_string(&move_max(&active))
-        );
-
-        // Create a mutable string to store the input
-        let mut input_text = String::new();
+/// How many chat lines are shown above the board.
+const CHAT_LINES: usize = 5;
+/// The longest chat message, in characters.
+const MAX_CHAT_LEN: usize = 200;
+
+/// Shortcuts for common chat messages, such as `/gg`.
+const EMOTES: [(&str, &str); 5] = [
+    ("gg", "good game!"),
+    ("glhf", "good luck, have fun!"),
+    ("wave", "*waves*"),
+    ("think", "*thinks hard*"),
+    ("oops", "oops!"),
+];
 
-        // Read the line from stdin, store it in input_text, and handle potential errors
-        io::stdin()
-            .read_line(&mut input_text)
-            .expect("Failed to read line");
-        input_text = input_text.trim().to_string();
+/// Turns a `/say <message>` or emote command into the message to send, or explains how to chat.
+fn parse_chat(command: &str) -> Result<String, String> {
+    let (name, text) = command.split_once(' ').unwrap_or((command, ""));
+
+    let text = match EMOTES.iter().find(|(emote, _)| *emote == name) {
+        Some((_, text)) => text,
+        None if name == "say" && !text.trim().is_empty() => text.trim(),
+        None => {
+            let emotes = EMOTES.map(|(emote, _)| format!("/{emote}"));
+            return Err(format!(
+                "Chat with `/say <message>` or one of {}",
+                emotes.join(", ")
+            ));
+        }
+    };
 
-        let pos = string_as_pos(&input_text);
-        pos
-    }
+    Ok(text.chars().take(MAX_CHAT_LEN).collect())
 }
 
-#[derive(Clone)]
-struct Random;
-impl PlayerTrait for Random {
-    fn play(
+trait PlayerTrait: Send + Sync {
+    /// Returns `None` if the player did not pick a position on the board.
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action>;
+
+    /// Like `play`, for games that nobody is watching, such as in `tut3 match`, so bots shouldn't
+    /// print anything.
+    fn play_quietly(
         &self,
-        _game: &Game,
-        _turn: &Piece,
-        _active: Option<(usize, usize)>,
-    ) -> Option<(usize, usize)> {
-        let x: u8 = rand::random();
-        let y: u8 = rand::random();
-        Some((x as usize % 9, y as usize % 9))
+        game: &Game,
+        turn: &Piece,
+        active: Option<(usize, usize)>,
+    ) -> Option<Action> {
+        self.play(game, turn, active)
     }
 }
 
-const WIN_SCORE: isize = 1_000_000;
-const MAX_DEPTH: usize = 8;
-struct Minimax;
-
-impl Minimax {
-    fn eval(game: &Game, me: &Piece) -> isize {
-        let mut points: isize = 0;
-
-        for x in 0..3 {
-            for y in 0..3 {
-                let subgame = game[x][y];
-
-                let subgame_won = subgame_won(&subgame);
-                if subgame_won == Piece::None {
-                    if subgame_is_draw(&subgame) {
-                        points -= 10;
-                    } else {
-                        points += subgame_score(&subgame, me);
-                    }
-                } else if subgame_won == *me {
-                    points += 100;
-                } else {
-                    points -= 100;
-                }
-            }
-        }
-
-        let game_won = game_won(&game);
-        if game_won == Piece::None {
-        } else if game_won == *me {
-            points += WIN_SCORE;
-        } else {
-            points -= WIN_SCORE;
-        }
-
-        points
-    }
-
-    fn play_inner(
-        depth: usize,
-        game: &GameState,
-        me: Piece,
-        mut alpha: isize,
-        beta: isize,
-    ) -> isize {
-        // terminal or cutoff
-        if depth >= MAX_DEPTH || game_won(&game.game) != Piece::None {
-            return Self::eval(&game.game, &me);
-        }
-
-        let mut best = isize::MIN;
-
-        for x1 in 0..3 {
-            for y1 in 0..3 {
-                if subgame_won(&game.game[x1][y1]) != Piece::None
-                    || subgame_is_draw(&game.game[x1][y1])
-                {
-                    continue;
-                }
+/// How long the smart bot thinks about a hint.
+const HINT_TIME: Duration = Duration::from_secs(1);
 
-                if let Some((x, y)) = game.active {
-                    if x1 != x || y1 != y {
-                        continue;
-                    }
-                }
-
-                for x0 in 0..3 {
-                    for y0 in 0..3 {
-                        if game.game[x1][y1][x0][y0] == Piece::None {
-                            let mut next = game.clone();
-
-                            if next.manual_turn(x1 * 3 + x0, y1 * 3 + y0) {
-                                // match game_won(&next.game) {
-                                //     p if p == me => return WIN_SCORE - depth as isize,
-                                //     p if p != Piece::None => return -WIN_SCORE + depth as isize,
-                                //     _ => {}
-                                // }
-
-                                // NEGAMAX RECURSION
-                                let score = -Self::play_inner(depth + 1, &next, me, -beta, -alpha);
-
-                                best = best.max(score);
-                                alpha = alpha.max(best);
-
-                                // ✂️ BETA CUTOFF
-                                if alpha >= beta {
-                                    return alpha;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+/// Asks the smart bot for the best move, and shows it on the board without playing it.
+fn show_hint(game: &Game, turn: &Piece, active: Option<(usize, usize)>) {
+    let budget = Budget {
+        time: Some(HINT_TIME),
+        ..Budget::default()
+    };
+    let board = Board::new(game, *turn, active);
+    let analysis = Minimax::new(budget, Level::EXPERT, Weights::default()).analyze(&board, true);
 
-        best
+    if let Some(&pos) = analysis.bests().first() {
+        print_game(game, &active, Some(pos));
+        println!("Hint: try {}", pos_as_string(&pos));
     }
 }
 
-impl PlayerTrait for Minimax {
-    fn play(
-        &self,
-        game: &Game,
-        turn: &Piece,
-        active: Option<(usize, usize)>,
-    ) -> Option<(usize, usize)> {
-        println!("Thinking...");
-        // let eval = Self::eval(game, turn);
-        // println!("Current score: {eval}");
+#[derive(Clone)]
+struct Human;
+impl PlayerTrait for Human {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        println!(
+            "It's {}'s turn! You can move in any open square between {} and {}, or type `hint`",
+            turn,
+            pos_as_string(&move_min(&active)),
+            pos_as_string(&move_max(&active))
+        );
 
-        let mut moves = Vec::new();
-        for x1 in 0..3 {
-            for y1 in 0..3 {
-                if subgame_won(&game[x1][y1]) != Piece::None || subgame_is_draw(&game[x1][y1]) {
-                    continue;
-                }
+        loop {
+            // Create a mutable string to store the input
+            let mut input_text = String::new();
 
-                if let Some((x, y)) = active {
-                    if !(x1 == x && y1 == y) {
-                        continue;
-                    }
-                }
+            // Read the line from stdin, store it in input_text, and handle potential errors
+            io::stdin()
+                .read_line(&mut input_text)
+                .expect("Failed to read line");
+            input_text = input_text.trim().to_string();
 
-                for x0 in 0..3 {
-                    for y0 in 0..3 {
-                        if game[x1][y1][x0][y0] == Piece::None {
-                            moves.push((x1, y1, x0, y0));
-                        }
-                    }
-                }
+            if input_text.eq_ignore_ascii_case("resign") {
+                return Some(Action::Resign);
             }
-        }
-
-        let mut bests = Vec::new();
-        let mut highest_score = isize::MIN;
-
-        let game_ = game;
-        let mut game = GameState::new(Rc::new(Minimax), Rc::new(Minimax));
-        game.game = *game_;
-        game.active = active;
-        game.turn = *turn;
-        for (x1, y1, x0, y0) in moves.iter() {
-            let mut game = game.clone();
-
-            if game.manual_turn(x1 * 3 + x0, y1 * 3 + y0) {
-                let score = Self::play_inner(0, &game, *turn, isize::MIN + 1, isize::MAX);
-                // println!("{}: {}", pos_as_string(&(x1 * 3 + x0, y1 * 3 + y0)), score);
-
-                if score > highest_score {
-                    bests.clear();
-                    bests.push((x1 * 3 + x0, y1 * 3 + y0));
-                    highest_score = score;
-                } else if score == highest_score {
-                    bests.push((x1 * 3 + x0, y1 * 3 + y0));
-                }
+            if input_text.eq_ignore_ascii_case("hint") || input_text == "?" {
+                show_hint(game, turn, active);
+                continue;
             }
-        }
-
-        if bests.len() == 0 {
-            let rand: u32 = rand::random();
-            let rand = rand as usize % moves.len();
-            let (x1, y1, x0, y0) = moves[rand];
 
-            bests.push((x1 * 3 + x0, y1 * 3 + y0));
+            let Some(command) = input_text.strip_prefix('/') else {
+                return string_as_pos(&input_text).map(Action::Move);
+            };
+            match parse_chat(command) {
+                Ok(text) => return Some(Action::Say(text)),
+                Err(help) => println!("{help}"),
+            }
         }
-
-        let rand: u32 = rand::random();
-        let rand = rand as usize % bests.len();
-
-        Some(bests[rand])
     }
 }
 
-struct Local(IpAddr);
-impl PlayerTrait for Local {
-    fn play(
-        &self,
-        game: &Game,
-        turn: &Piece,
-        active: Option<(usize, usize)>,
-    ) -> Option<(usize, usize)> {
-        let game_ = game;
-        let mut game = GameState::new(Rc::new(Human), Rc::new(Human));
-        game.game = *game_;
-        game.active = active;
-        game.turn = *turn;
-
-        let pos = game.turn();
-        let update = game.update_to_bytes();
-
-        if let Ok(socket) = UdpSocket::bind("0.0.0.0") {
-            socket
-                .send_to(&update, (self.0, 2003))
-                .expect("Failed to send game update");
-        } else {
-            panic!("Failed to bind socket")
-        }
-
-        pos
-    }
+/// A bot that plays a random legal move, picked by `policy`.
+#[derive(Clone)]
+struct Random {
+    policy: Policy,
 }
-
-struct Remote(IpAddr);
-
-impl PlayerTrait for Remote {
-    fn play(
-        &self,
-        _game: &Game,
-        _turn: &Piece,
-        _active: Option<(usize, usize)>,
-    ) -> Option<(usize, usize)> {
-        // if let Ok(socket) = UdpSocket::bind("0.0.0.0") {
-        //     socket.connect(addr)
-        // }
-
-        None
+impl PlayerTrait for Random {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        let board = Board::new(game, *turn, active);
+        self.policy.pick(&board, &mut rand::rng()).map(Action::Move)
     }
 }
 
@@ -593,55 +559,27 @@ fn new_game() -> Game {
     [[new_empty_subgame(); 3]; 3]
 }
 
-fn subgame_won(subgame: &Subgame) -> Piece {
-    for win in WINS {
-        let a = &subgame[win[0].0][win[0].1];
-        let b = &subgame[win[1].0][win[1].1];
-        let c = &subgame[win[2].0][win[2].1];
-        if a != &Piece::None && a == b && a == c {
-            return a.clone();
+/// Looks a sub-board up in the tables the bots use.
+fn subgame_info(subgame: &Subgame) -> &'static SubInfo {
+    let (mut x, mut o) = (0, 0);
+    for (x0, column) in subgame.iter().enumerate() {
+        for (y0, piece) in column.iter().enumerate() {
+            match piece {
+                Piece::X => x |= 1 << (x0 * 3 + y0),
+                Piece::O => o |= 1 << (x0 * 3 + y0),
+                Piece::None => {}
+            }
         }
     }
-    Piece::None
+    SubInfo::of(x, o)
 }
 
-fn subgame_score(subgame: &Subgame, me: &Piece) -> isize {
-    if me == &Piece::None {
-        return 0;
-    }
-
-    let mut score = 0;
-    for win in WINS {
-        let a = &subgame[win[0].0][win[0].1];
-        let b = &subgame[win[1].0][win[1].1];
-        let c = &subgame[win[2].0][win[2].1];
-        let all = [a, b, c];
-        let me_count = all.iter().filter(|piece| piece == &&me).count();
-        let them_count = all.iter().filter(|piece| piece == &&&me.other()).count();
-
-        if me_count == 2 && them_count == 0 {
-            score += 1;
-        }
-
-        if me_count == 0 && them_count == 2 {
-            score -= 1;
-        }
-    }
-    score
+fn subgame_won(subgame: &Subgame) -> Piece {
+    subgame_info(subgame).winner
 }
 
 fn subgame_is_draw(subgame: &Subgame) -> bool {
-    for win in WINS {
-        let a = &subgame[win[0].0][win[0].1];
-        let b = &subgame[win[1].0][win[1].1];
-        let c = &subgame[win[2].0][win[2].1];
-        let all = [a, b, c];
-
-        if !(all.contains(&&Piece::X) && all.contains(&&Piece::O)) {
-            return false;
-        }
-    }
-    true
+    subgame_info(subgame).blocked
 }
 
 fn game_won(game: &Game) -> Piece {
@@ -656,27 +594,98 @@ fn game_won(game: &Game) -> Piece {
     Piece::None
 }
 
+/// Whether the game is a draw: every line of the whole board goes through a drawn sub-board, or
+/// nobody has won and there is nowhere left to play.
 fn game_is_draw(game: &Game) -> bool {
+    let playable = game
+        .iter()
+        .flatten()
+        .any(|subgame| subgame_won(subgame) == Piece::None && !subgame_is_draw(subgame));
+    if !playable {
+        return game_won(game) == Piece::None;
+    }
+
     for win in WINS {
         let a = subgame_is_draw(&game[win[0].0][win[0].1]);
         let b = subgame_is_draw(&game[win[1].0][win[1].1]);
         let c = subgame_is_draw(&game[win[2].0][win[2].1]);
 
-        if a == false && a == b && a == c {
+        if !a && a == b && a == c {
             return false;
         }
     }
     true
 }
 
-fn print_game(game: &Game, active: &Option<(usize, usize)>) {
+/// Checks whether the piece to move may be placed at `pos`, returning the reason if it may not.
+fn check_move(
+    game: &Game,
+    active: &Option<(usize, usize)>,
+    (x, y): (usize, usize),
+) -> Result<(), &'static str> {
+    if x >= 9 || y >= 9 {
+        return Err("That position is not within the game boundaries!");
+    }
+
+    let x0 = x % 3;
+    let y0 = y % 3;
+    let x1 = x / 3;
+    let y1 = y / 3;
+
+    if subgame_won(&game[x1][y1]) != Piece::None {
+        return Err("That position is within a game that has already been won!");
+    }
+
+    if subgame_is_draw(&game[x1][y1]) {
+        return Err("That position is within a game that has already been drawn!");
+    }
+
+    if let Some((ax, ay)) = active
+        && (x1 != *ax || y1 != *ay)
+    {
+        return Err("That position is not within the current active game!");
+    }
+
+    if game[x1][y1][x0][y0] != Piece::None {
+        return Err("There is already a piece at that position!");
+    }
+
+    Ok(())
+}
+
+fn pieces_placed(game: &Game) -> u8 {
+    game.iter()
+        .flatten()
+        .flatten()
+        .flatten()
+        .filter(|piece| **piece != Piece::None)
+        .count() as u8
+}
+
+/// The CRC-32 (IEEE) checksum used by game state snapshots.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Prints the board, marking the cell at `hint` if there is one.
+fn print_game(game: &Game, active: &Option<(usize, usize)>, hint: Option<(usize, usize)>) {
     let show_active = active.is_some();
     let (active_x, active_y) = match active {
         Some((x, y)) => (*x, *y),
         None => (0, 0),
     };
 
-    println!("{}", "\n".repeat(100));
     if show_active && active_x == 0 {
         println!("     a   b   c");
     } else if show_active && active_x == 1 {
@@ -691,7 +700,7 @@ fn print_game(game: &Game, active: &Option<(usize, usize)>) {
         //print!("   |");
         let y0 = y % 3;
         let y1 = y / 3;
-        if show_active == false || y1 == active_y {
+        if !show_active || y1 == active_y {
             print!(" {} |", y + 1);
         } else {
             print!("   |");
@@ -705,6 +714,8 @@ fn print_game(game: &Game, active: &Option<(usize, usize)>) {
 
             if subgame_is_draw(&game[x1][y1]) {
                 print!(" ⋅ ");
+            } else if hint == Some((x, y)) {
+                print!(" {GREEN}*{RESET} ");
             } else if winner == Piece::None {
                 let piece = game[x1][y1][x0][y0];
                 print!(" {piece} ");
@@ -782,7 +793,7 @@ fn print_game(game: &Game, active: &Option<(usize, usize)>) {
                     }
                 }
             }
-            println!("");
+            println!();
 
             if y0 == 2 {
                 if y % 3 != 2 {
@@ -806,7 +817,7 @@ fn print_game(game: &Game, active: &Option<(usize, usize)>) {
                         print!("+");
                     }
                 }
-                println!("");
+                println!();
             }
         }
     }
@@ -835,74 +846,420 @@ fn string_as_pos(pos: &str) -> Option<(usize, usize)> {
     let mut chars = pos.to_lowercase().chars().collect::<Vec<_>>();
     if chars.len() != 2 {
         return None;
-    } else {
-        let rank = chars.pop().unwrap();
-        let file = chars.pop().unwrap();
+    }
 
-        let file_i = FILES
-            .iter()
-            .enumerate()
-            .find_map(|(i, f)| if f == &file { Some(i) } else { None });
-        let rank_i = rank.to_digit(10);
-        if rank_i.is_none() || file_i.is_none() || rank_i.unwrap() < 1 {
-            return None;
-        }
+    let rank = chars.pop().unwrap();
+    let file = chars.pop().unwrap();
 
-        Some((file_i.unwrap(), rank_i.unwrap() as usize - 1))
+    let file_i = FILES
+        .iter()
+        .enumerate()
+        .find_map(|(i, f)| if f == &file { Some(i) } else { None });
+    let rank_i = rank.to_digit(10);
+    if rank_i.is_none() || file_i.is_none() || rank_i.unwrap() < 1 {
+        return None;
+    }
+
+    Some((file_i.unwrap(), rank_i.unwrap() as usize - 1))
+}
+
+/// Reads a player type, such as `human` or `smart`. Bots can be given an option after a colon:
+/// a level for the smart bot, e.g. `smart:easy` or `smart:2`, and a policy for the random and
+/// mcts bots, e.g. `random:greedy`.
+fn player_from_string(string: &str, budget: Budget, weights: Weights) -> Option<Player> {
+    let string = string.to_lowercase();
+    let (name, option) = match string.split_once(':') {
+        Some((name, option)) => (name, Some(option)),
+        None => (string.as_str(), None),
+    };
+    let policy = || option.map_or(Some(Policy::Uniform), Policy::from_name);
+
+    match (name, option) {
+        ("human", None) => Some(Arc::new(Human)),
+        ("random", _) => Some(Arc::new(Random { policy: policy()? })),
+        ("smart", level) => Some(Arc::new(Minimax::new(
+            budget,
+            level.map_or(Some(Level::EXPERT), Level::from_name)?,
+            weights,
+        ))),
+        ("mcts", _) => Some(Arc::new(mcts::Mcts::new(budget, policy()?))),
+        _ => None,
     }
 }
 
-fn player_from_string(string: &str) -> Option<Player> {
+fn piece_from_string(string: &str) -> Option<Piece> {
     match string.to_lowercase().as_str() {
-        "human" => Some(Rc::new(Human)),
-        "random" => Some(Rc::new(Random)),
-        "smart" => Some(Rc::new(Minimax)),
-        other => match IpAddr::from_str(other) {
-            Ok(addr) => Some(Rc::new(Remote(addr))),
-            Err(_) => None,
-        },
+        "x" => Some(Piece::X),
+        "o" => Some(Piece::O),
+        _ => None,
+    }
+}
+
+/// Removes `flag` and the value after it from `args`, returning the value.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.remove(i);
+    if i < args.len() {
+        Some(args.remove(i))
+    } else {
+        panic!("{flag} needs a value");
+    }
+}
+
+/// Removes `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let found = args.iter().any(|arg| arg == flag);
+    args.retain(|arg| arg != flag);
+    found
+}
+
+/// The name of the person running tut3, if the system knows it.
+fn user_name() -> Option<String> {
+    env::var("USER").or_else(|_| env::var("USERNAME")).ok()
+}
+
+fn print_result(game: &GameState) {
+    if game.is_draw() {
+        println!("It's a draw!");
+    } else if game.resigned != Piece::None {
+        println!("{} resigned, {} wins!", game.resigned, game.won());
+    } else {
+        println!("{} wins!", game.won());
     }
 }
 
 fn main() {
-    let mut player_1: Player = Rc::new(Human);
-    let mut player_2: Player = Rc::new(Human);
+    let mut player_1: Player = Arc::new(Human);
+    let mut player_2: Player = Arc::new(Human);
+
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let key = take_option(&mut args, "--password").map(|password| auth::Key::derive(&password));
+    let nodes = take_option(&mut args, "--nodes")
+        .map(|nodes| nodes.parse().expect("--nodes must be a number"));
+    let time = take_option(&mut args, "--time").map(|time| {
+        Duration::from_secs_f64(time.parse().expect("--time must be a number of seconds"))
+    });
+    let threads = take_option(&mut args, "--threads")
+        .map(|threads| threads.parse().expect("--threads must be a number"));
+    // a node limit on its own plays the same however fast the computer is, which several threads
+    // racing each other would not
+    let budget = Budget {
+        time: time.or(if nodes.is_some() {
+            None
+        } else {
+            Budget::default().time
+        }),
+        nodes,
+        threads: threads
+            .or(nodes.map(|_| 1))
+            .unwrap_or(Budget::default().threads)
+            .max(1),
+    };
+
+    // how long a player who loses the connection has to come back
+    let grace = take_option(&mut args, "--grace").map_or(Duration::from_secs(60), |grace| {
+        Duration::from_secs(grace.parse().expect("--grace must be a number of seconds"))
+    });
+
+    let weights = take_option(&mut args, "--weights").map_or_else(Weights::default, |path| {
+        fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read {path}: {e}"))
+            .parse()
+            .unwrap_or_else(|e| panic!("Failed to read {path}: {e}"))
+    });
+
+    match args.first().map(|arg| arg.to_lowercase()).as_deref() {
+        Some("host") => {
+            let name = take_option(&mut args, "--name").unwrap_or_else(|| match user_name() {
+                Some(user) => format!("{user}'s game"),
+                None => "tut3 game".to_string(),
+            });
+            let side = take_option(&mut args, "--side")
+                .map(|side| piece_from_string(&side).expect("--side must be either 'x' or 'o'"))
+                .unwrap_or(Piece::X);
+            let player = args.get(1).map_or_else(
+                || Arc::new(Human) as Player,
+                |player| player_from_string(player, budget, weights).expect("Unknown player type"),
+            );
+            discovery::host(name, side, player, grace, key);
+            return;
+        }
+        Some("join") => {
+            let player = args.get(1).map_or_else(
+                || Arc::new(Human) as Player,
+                |player| player_from_string(player, budget, weights).expect("Unknown player type"),
+            );
+            discovery::join(player, key);
+            return;
+        }
+        Some("serve") => {
+            let port = take_option(&mut args, "--port")
+                .map(|port| port.parse().expect("--port must be a number"))
+                .unwrap_or(net::PORT);
+            server::serve(port, grace, key);
+            return;
+        }
+        Some("connect") => {
+            let side = take_option(&mut args, "--side")
+                .map(|side| piece_from_string(&side).expect("--side must be either 'x' or 'o'"))
+                .unwrap_or(Piece::None);
+            let rejoin = take_option(&mut args, "--rejoin").map(|code| {
+                server::RejoinCode::parse(&code).expect("--rejoin needs the code shown in the game")
+            });
+            let addr = args.get(1).expect("Usage: tut3 connect <server> [player]");
+            let player = args.get(2).map_or_else(
+                || Arc::new(Human) as Player,
+                |player| player_from_string(player, budget, weights).expect("Unknown player type"),
+            );
+            server::connect(addr, side, rejoin, player, key);
+            return;
+        }
+        Some("lobby") => {
+            let port = take_option(&mut args, "--port")
+                .map(|port| port.parse().expect("--port must be a number"))
+                .unwrap_or(net::PORT);
+            let name = take_option(&mut args, "--name");
+
+            match args.get(1) {
+                None => lobby::serve(port, grace, key),
+                Some(addr) => {
+                    let player = args.get(2).map(|player| {
+                        player_from_string(player, budget, weights).expect("Unknown player type")
+                    });
+                    let name = name
+                        .or_else(user_name)
+                        .expect("Pick a name with `--name <name>`");
+                    lobby::enter(addr, name, player, key);
+                }
+            }
+            return;
+        }
+        Some("analyze") => {
+            analyze::analyze(&args[1..], budget, weights);
+            return;
+        }
+        Some("tune") => {
+            let out = take_option(&mut args, "--out").unwrap_or_else(|| "weights.txt".to_string());
+            let rounds = args.get(1).map_or(100, |rounds| {
+                rounds
+                    .parse()
+                    .expect("The number of rounds must be a number")
+            });
+            // short searches, so that there is time for lots of games
+            let budget = Budget {
+                time,
+                nodes: nodes.or(Some(tune::NODES)),
+                threads: threads.unwrap_or(1),
+            };
+            tune::tune(weights, rounds, budget, Path::new(&out));
+            return;
+        }
+        Some("match") => {
+            let games = take_option(&mut args, "--games").map_or(100, |games| {
+                games.parse().expect("--games must be a number")
+            });
+            let sprt = take_option(&mut args, "--sprt").map(|sprt| {
+                sprt.split_once(',')
+                    .and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)))
+                    .expect("--sprt needs two Elo differences, e.g. `--sprt 0,20`")
+            });
+            let (Some(a), Some(b)) = (args.get(1), args.get(2)) else {
+                panic!("Usage: tut3 match <bot> <bot> [--games <n>] [--sprt <elo0>,<elo1>]");
+            };
+            let bot = |name: &str| {
+                if name.to_lowercase().starts_with("human") {
+                    panic!("Only bots can play in a match");
+                }
+                player_from_string(name, budget, weights).expect("Unknown player type")
+            };
+            arena::play_match([a, b], bot(a), bot(b), games, sprt);
+            return;
+        }
+        Some("watch") => {
+            let addr = args
+                .get(1)
+                .expect("Usage: tut3 watch <address> [game number]");
+            let game = args.get(2).map_or(0, |game| {
+                game.parse().expect("The game number must be a number")
+            });
+            spectate::watch(addr, game, key);
+            return;
+        }
+        _ => {}
+    }
+
+    let spectators =
+        take_flag(&mut args, "--spectators").then(|| spectate::listen(net::PORT, key.clone()));
 
-    let mut args = env::args();
-    args.next();
+    let rejoin = take_option(&mut args, "--rejoin").map(|code| {
+        server::RejoinCode::parse(&code).expect("--rejoin needs the code shown in the game")
+    });
 
+    let mut args = args.into_iter();
     if let Some(next) = args.next() {
         if let Ok(addr) = IpAddr::from_str(&next) {
-            player_1 = match args.next().unwrap().as_str() {
-                "local" => Rc::new(Local(addr)),
-                "remote" => Rc::new(Remote(addr)),
-                _ => panic!("Must be either 'local' or 'remote'"),
-            };
+            let sides = [
+                args.next().unwrap_or_default().to_lowercase(),
+                args.next().unwrap_or_default().to_lowercase(),
+            ];
+            if sides.iter().filter(|side| *side == "remote").count() != 1 {
+                panic!("Exactly one player must be 'remote', e.g. `tut3 {addr} local remote`");
+            }
 
-            player_2 = match args.next().unwrap().as_str() {
-                "local" => Rc::new(Local(addr)),
-                "remote" => Rc::new(Remote(addr)),
-                _ => panic!("Must be either 'local' or 'remote'"),
+            // `local` is a human at this machine, any other player type plays from here too
+            let (side, local) = if sides[1] == "remote" {
+                (Piece::X, &sides[0])
+            } else {
+                (Piece::O, &sides[1])
             };
+            let player = match local.as_str() {
+                "local" => Arc::new(Human),
+                other => player_from_string(other, budget, weights)
+                    .expect("Must be either 'local', 'remote' or a player type"),
+            };
+
+            if let Some(code) = rejoin {
+                net::rejoin(addr, code, player, grace, key);
+                return;
+            }
+            let session = net::Session::connect_or_host(addr, side, key.as_ref())
+                .unwrap_or_else(|e| panic!("Failed to start a game with {addr}: {e}"));
+            net::play(session, player, grace, key);
+            return;
         } else {
-            player_1 = player_from_string(&next).unwrap();
+            player_1 = player_from_string(&next, budget, weights).unwrap();
 
             if let Some(next) = args.next() {
-                player_2 = player_from_string(&next).unwrap();
+                player_2 = player_from_string(&next, budget, weights).unwrap();
             }
         }
     }
 
+    if let Some(hub) = &spectators {
+        player_1 = Arc::new(spectate::Spectated {
+            player: player_1,
+            hub: hub.clone(),
+        });
+        player_2 = Arc::new(spectate::Spectated {
+            player: player_2,
+            hub: hub.clone(),
+        });
+    }
+
     let mut game = GameState::new(player_1, player_2);
+    if let Some(hub) = &spectators {
+        hub.publish_game(&game);
+    }
+
     while !game.is_complete() {
         game.print();
         game.turn();
+
+        if let Some(hub) = &spectators {
+            hub.publish_game(&game);
+        }
     }
 
     game.print();
-    if game.is_draw() {
-        println!("It's a draw!");
-    } else {
-        println!("{} wins!", game.turn);
+    print_result(&game);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A game after e5 e4, with X to move in the top middle sub-board.
+    fn game() -> GameState {
+        let mut game = GameState::new(Arc::new(Human), Arc::new(Human));
+        for pos in ["e5", "e4"] {
+            let (x, y) = string_as_pos(pos).unwrap();
+            assert!(game.manual_turn(x, y));
+        }
+        game
+    }
+
+    /// Replaces the checksum at the end of `bytes` with one that matches the rest.
+    fn reseal(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - 4);
+        bytes.extend(crc32(bytes).to_be_bytes());
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let game = game();
+        let mut restored = GameState::new(Arc::new(Human), Arc::new(Human));
+        assert_eq!(restored.update_from_bytes(&game.update_to_bytes()), Ok(()));
+        assert_eq!(restored.game, game.game);
+        assert_eq!(restored.active, game.active);
+        assert_eq!(restored.turn, game.turn);
+    }
+
+    #[test]
+    fn state_rejects_bad_snapshots() {
+        let mut game = game();
+        let bytes = game.update_to_bytes();
+        // magic, version, active game, turn, 81 cells, move number, checksum
+        let (turn, cells, number) = (8, 9, 90);
+
+        let mut flipped = bytes.clone();
+        flipped[cells + 40] ^= 1;
+        assert_eq!(
+            game.update_from_bytes(&flipped),
+            Err(StateError::ChecksumMismatch)
+        );
+
+        assert_eq!(
+            game.update_from_bytes(&bytes[..3]),
+            Err(StateError::TooShort)
+        );
+        let mut truncated = bytes[..number].to_vec();
+        truncated.extend([0; 4]);
+        reseal(&mut truncated);
+        assert_eq!(
+            game.update_from_bytes(&truncated),
+            Err(StateError::TooShort)
+        );
+
+        let mut bad_piece = bytes.clone();
+        bad_piece[turn] = 7;
+        reseal(&mut bad_piece);
+        assert_eq!(
+            game.update_from_bytes(&bad_piece),
+            Err(StateError::InvalidPiece(7))
+        );
+
+        let mut wrong_number = bytes.clone();
+        wrong_number[number] = 5;
+        reseal(&mut wrong_number);
+        assert_eq!(
+            game.update_from_bytes(&wrong_number),
+            Err(StateError::WrongMoveNumber(5))
+        );
+
+        let mut no_turn = bytes.clone();
+        no_turn[turn] = 0;
+        reseal(&mut no_turn);
+        assert_eq!(
+            game.update_from_bytes(&no_turn),
+            Err(StateError::MissingTurn)
+        );
+
+        // X has won the top middle sub-board it is being sent to
+        let mut won = game.clone();
+        won.game[1][0][0] = [Piece::X; 3];
+        assert_eq!(
+            game.update_from_bytes(&won.update_to_bytes()),
+            Err(StateError::FinishedActive)
+        );
+        let (x, o) = (Piece::X, Piece::O);
+        let mut full = game.clone();
+        full.game[1][0] = [[x, o, x], [x, o, o], [o, x, x]];
+        assert_eq!(
+            game.update_from_bytes(&full.update_to_bytes()),
+            Err(StateError::FinishedActive)
+        );
+
+        // none of the bad snapshots changed the game
+        assert_eq!(game.update_to_bytes(), bytes);
     }
 }
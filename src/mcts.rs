@@ -0,0 +1,165 @@
+use std::time::Instant;
+
+use rand::Rng;
+
+use crate::{Action, Game, Piece, PlayerTrait, board::Board, minimax::Budget, policy::Policy};
+
+/// How much UCT favours trying moves it knows little about over the ones that have done well.
+const EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+struct Node {
+    /// The move that led here from the parent, and who played it.
+    pos: (usize, usize),
+    mover: Piece,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// The moves that don't have a node yet.
+    untried: Vec<(usize, usize)>,
+    visits: u32,
+    /// The playouts through here that `mover` won, with a draw counting as half.
+    score: f64,
+}
+
+impl Node {
+    fn uct(&self, parent_visits: u32) -> f64 {
+        let visits = self.visits as f64;
+        self.score / visits + EXPLORATION * ((parent_visits as f64).ln() / visits).sqrt()
+    }
+}
+
+/// A bot that plays out lots of random games and picks the move that did best, spending more of
+/// its playouts on the moves that look good so far (Monte Carlo tree search with UCT).
+pub struct Mcts {
+    budget: Budget,
+    /// How the moves of the playouts are picked.
+    policy: Policy,
+}
+
+impl Mcts {
+    pub fn new(budget: Budget, policy: Policy) -> Mcts {
+        Mcts { budget, policy }
+    }
+
+    /// Plays moves picked by the policy until the game is over and returns who won, or
+    /// `Piece::None` for a draw.
+    fn playout(&self, mut board: Board, rng: &mut impl Rng) -> Piece {
+        while !board.is_over() {
+            match self.policy.pick(&board, rng) {
+                Some(pos) => {
+                    board.apply_move(pos);
+                }
+                None => break,
+            }
+        }
+        board.winner()
+    }
+}
+
+impl PlayerTrait for Mcts {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        println!("Thinking...");
+        self.play_quietly(game, turn, active)
+    }
+
+    fn play_quietly(
+        &self,
+        game: &Game,
+        turn: &Piece,
+        active: Option<(usize, usize)>,
+    ) -> Option<Action> {
+        let root = Board::new(game, *turn, active);
+        let mut nodes = vec![Node {
+            pos: (0, 0),
+            mover: turn.other(),
+            parent: None,
+            children: Vec::new(),
+            untried: root.moves().to_vec(),
+            visits: 0,
+            score: 0.0,
+        }];
+
+        let mut rng = rand::rng();
+        let start = Instant::now();
+        let mut playouts = 0;
+        loop {
+            // always play out at least one game, so there is a move to pick
+            if playouts > 0
+                && (self.budget.nodes.is_some_and(|limit| playouts >= limit)
+                    || self.budget.time.is_some_and(|time| start.elapsed() >= time))
+            {
+                break;
+            }
+            playouts += 1;
+
+            // selection: follow the best child until a node has moves without a child
+            let mut node = 0;
+            let mut board = root;
+            while nodes[node].untried.is_empty() && !nodes[node].children.is_empty() {
+                let parent_visits = nodes[node].visits;
+                node = *nodes[node]
+                    .children
+                    .iter()
+                    .max_by(|a, b| {
+                        nodes[**a]
+                            .uct(parent_visits)
+                            .total_cmp(&nodes[**b].uct(parent_visits))
+                    })
+                    .unwrap();
+                board.apply_move(nodes[node].pos);
+            }
+
+            // expansion: add one of the moves that haven't been tried
+            if !nodes[node].untried.is_empty() {
+                let i = rng.random_range(0..nodes[node].untried.len());
+                let pos = nodes[node].untried.swap_remove(i);
+                let mover = board.turn();
+                board.apply_move(pos);
+
+                let child = nodes.len();
+                nodes.push(Node {
+                    pos,
+                    mover,
+                    parent: Some(node),
+                    children: Vec::new(),
+                    // nothing can be played after the game is over
+                    untried: if !board.is_over() {
+                        board.moves().to_vec()
+                    } else {
+                        Vec::new()
+                    },
+                    visits: 0,
+                    score: 0.0,
+                });
+                nodes[node].children.push(child);
+                node = child;
+            }
+
+            // simulation
+            let winner = self.playout(board, &mut rng);
+
+            // backpropagation
+            let mut next = Some(node);
+            while let Some(node) = next {
+                let node = &mut nodes[node];
+                node.visits += 1;
+                node.score += if winner == Piece::None {
+                    0.5
+                } else if winner == node.mover {
+                    1.0
+                } else {
+                    0.0
+                };
+                next = node.parent;
+            }
+        }
+
+        // the most played move is the one the search trusts most, if there is a move at all
+        let best = nodes[0]
+            .children
+            .iter()
+            .max_by_key(|child| nodes[**child].visits)
+            .map(|child| nodes[*child].pos)?;
+
+        Some(Action::Move(best))
+    }
+}
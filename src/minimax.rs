@@ -0,0 +1,799 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, seq::SliceRandom};
+
+use crate::{Action, Game, Piece, PlayerTrait, board::Board, eval::Weights};
+
+/// The score of winning right away. Wins further off score one less for every move until the game
+/// is over, so that the search goes for the quickest win and puts off a loss as long as it can.
+pub const WIN_SCORE: isize = 1_000_000;
+/// The most moves a game can last, so any score within this of `WIN_SCORE` is a forced win.
+pub const MAX_PLIES: isize = 81;
+/// How many positions are searched between looks at the clock and at whether the other threads
+/// have finished.
+const CLOCK_INTERVAL: u64 = 1024;
+/// The number of entries in the transposition table, which must be a power of two.
+const TABLE_SIZE: usize = 1 << 20;
+/// How often the progress of a search is shown while it runs.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a stored score says about the real score of a position.
+#[derive(Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    /// The search was cut off, so the real score is at least this.
+    Lower,
+    /// No move reached alpha, so the real score is at most this.
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    hash: u64,
+    /// How many more moves were searched below the position.
+    depth: u8,
+    score: isize,
+    bound: Bound,
+    best: Option<(usize, usize)>,
+}
+
+impl Entry {
+    /// Packs everything but the hash into one word: the score in the low 32 bits, then the depth,
+    /// the bound, and the best move as `x * 9 + y + 1`, or 0 for none.
+    fn pack(&self) -> u64 {
+        let score = self.score.clamp(i32::MIN as isize, i32::MAX as isize) as i32 as u32 as u64;
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let best = self.best.map_or(0, |(x, y)| x * 9 + y + 1) as u64;
+        score | (self.depth as u64) << 32 | bound << 40 | best << 42
+    }
+
+    fn unpack(hash: u64, data: u64) -> Entry {
+        let best = (data >> 42) as usize & 0x7f;
+        Entry {
+            hash,
+            depth: (data >> 32) as u8,
+            score: data as u32 as i32 as isize,
+            bound: match (data >> 40) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best: (best != 0).then(|| ((best - 1) / 9, (best - 1) % 9)),
+        }
+    }
+}
+
+/// Remembers the results of earlier searches by Zobrist hash, so that a position reached by
+/// different move orders is only searched once. Each hash has one slot, and a deeper search of
+/// the same position or any search of another position replaces what is in it.
+///
+/// Every search thread shares the table without locking it. A slot holds the packed entry and the
+/// hash xored with it, so if two threads write the same slot at once and the halves come from
+/// different entries, the hash no longer matches and the slot reads as empty.
+struct Table {
+    slots: Vec<[AtomicU64; 2]>,
+}
+
+impl Table {
+    fn new() -> Table {
+        Table {
+            slots: (0..TABLE_SIZE)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    fn get(&self, hash: u64) -> Option<Entry> {
+        let [check, data] = &self.slots[hash as usize & (TABLE_SIZE - 1)];
+        let (check, data) = (check.load(Ordering::Relaxed), data.load(Ordering::Relaxed));
+        (check ^ data == hash).then(|| Entry::unpack(hash, data))
+    }
+
+    fn put(&self, entry: Entry) {
+        if self
+            .get(entry.hash)
+            .is_some_and(|old| old.depth > entry.depth)
+        {
+            return;
+        }
+
+        let [check, data] = &self.slots[entry.hash as usize & (TABLE_SIZE - 1)];
+        let packed = entry.pack();
+        check.store(entry.hash ^ packed, Ordering::Relaxed);
+        data.store(packed, Ordering::Relaxed);
+    }
+}
+
+/// How long the smart and mcts bots may think about each move.
+#[derive(Clone, Copy)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    /// The most positions to search, or games to play out for the mcts bot, so that the bot plays
+    /// the same on any computer.
+    pub nodes: Option<u64>,
+    /// How many threads the smart bot searches with. They share one transposition table, so the
+    /// extra threads mostly fill it with positions the first one is about to need.
+    pub threads: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget {
+            time: Some(Duration::from_secs(2)),
+            nodes: None,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+}
+
+/// How well the smart bot plays. The weaker levels look fewer moves ahead, misjudge positions a
+/// little and now and then play a random move, so that people learning the game can beat them.
+#[derive(Clone, Copy)]
+pub struct Level {
+    /// The most moves to look ahead, counting the bot's own, or `None` to look as far as the budget
+    /// allows.
+    pub depth: Option<usize>,
+    /// The most that each move's score is randomly raised or lowered by.
+    pub noise: i32,
+    /// The chance of playing a random legal move instead of thinking.
+    pub mistakes: f64,
+}
+
+impl Level {
+    pub const EASY: Level = Level {
+        depth: Some(1),
+        noise: 40,
+        mistakes: 0.3,
+    };
+    pub const MEDIUM: Level = Level {
+        depth: Some(3),
+        noise: 15,
+        mistakes: 0.1,
+    };
+    pub const HARD: Level = Level {
+        depth: Some(5),
+        noise: 3,
+        mistakes: 0.02,
+    };
+    pub const EXPERT: Level = Level {
+        depth: None,
+        noise: 0,
+        mistakes: 0.0,
+    };
+
+    /// Reads a level by name, such as `easy`, or by number, from `1` for easy to `4` for expert.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name.to_lowercase().as_str() {
+            "easy" | "1" => Some(Level::EASY),
+            "medium" | "2" => Some(Level::MEDIUM),
+            "hard" | "3" => Some(Level::HARD),
+            "expert" | "4" => Some(Level::EXPERT),
+            _ => None,
+        }
+    }
+}
+
+pub struct Minimax {
+    table: Table,
+    budget: Budget,
+    level: Level,
+    weights: Weights,
+}
+
+impl Minimax {
+    pub fn new(budget: Budget, level: Level, weights: Weights) -> Minimax {
+        Minimax {
+            table: Table::new(),
+            budget,
+            level,
+            weights,
+        }
+    }
+}
+
+/// One search to a fixed depth, which gives up when the budget runs out.
+struct Search<'a> {
+    table: &'a Table,
+    weights: &'a Weights,
+    /// Set once the first thread has picked its move, to stop the others.
+    finished: &'a AtomicBool,
+    max_depth: usize,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+    nodes: u64,
+    stopped: bool,
+    /// Whether any line ended at `max_depth` rather than at the end of the game, i.e. whether a
+    /// deeper search could find anything new.
+    horizon: bool,
+    /// The best line found below each depth, or what is left of it after cutoffs.
+    pv: Vec<Vec<(usize, usize)>>,
+    /// The last two moves at each depth that caused a cutoff, which often do again in the
+    /// positions next to it.
+    killers: Vec<[Option<(usize, usize)>; 2]>,
+    /// How much each move of each side has caused cutoffs, weighted towards deeper searches.
+    history: [[u32; 81]; 2],
+    /// How many positions were cut off, and how many of those by the first move tried, which
+    /// shows how well the moves are ordered.
+    cutoffs: u64,
+    first_cutoffs: u64,
+}
+
+impl Search<'_> {
+    /// Scores `board` from the point of view of the side to move, `depth` moves below the root
+    /// moves.
+    fn play_inner(
+        &mut self,
+        depth: usize,
+        board: &mut Board,
+        mut alpha: isize,
+        mut beta: isize,
+    ) -> isize {
+        if self.stopped {
+            return 0;
+        }
+        self.nodes += 1;
+        if self.pv.len() <= depth + 1 {
+            self.pv.resize(depth + 2, Vec::new());
+            self.killers.resize(depth + 2, [None; 2]);
+        }
+        self.pv[depth].clear();
+
+        // the root move and the moves below it
+        let ply = depth as isize + 1;
+
+        // terminal or cutoff
+        if board.winner() != Piece::None {
+            // only the side that just moved can have won
+            return -(WIN_SCORE - ply);
+        }
+        if board.is_draw() {
+            return 0;
+        }
+        if depth >= self.max_depth {
+            self.horizon = true;
+            return self.weights.eval(board, board.turn());
+        }
+
+        if self.node_limit.is_some_and(|limit| self.nodes >= limit)
+            || (self.nodes.is_multiple_of(CLOCK_INTERVAL)
+                && (self.finished.load(Ordering::Relaxed)
+                    || self
+                        .deadline
+                        .is_some_and(|deadline| Instant::now() >= deadline)))
+        {
+            self.stopped = true;
+            return 0;
+        }
+
+        let hash = board.hash();
+        let remaining = self.max_depth - depth;
+        let mut moves = board.moves();
+        if moves.is_empty() {
+            return 0;
+        }
+
+        let mut tt_move = None;
+        if let Some(entry) = self.table.get(hash) {
+            let score = from_table(entry.score, ply);
+            if entry.depth as usize >= remaining {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower => alpha = alpha.max(score),
+                    Bound::Upper => beta = beta.min(score),
+                }
+                if alpha >= beta {
+                    return score;
+                }
+            }
+            tt_move = entry.best;
+        }
+        if remaining > 1 {
+            self.order(&mut moves, board, depth, tt_move);
+        } else if let Some(best) = tt_move
+            && let Some(i) = moves.iter().position(|pos| *pos == best)
+        {
+            // the moves below are only evaluated, which is cheaper than sorting them
+            moves[..=i].rotate_right(1);
+        }
+
+        let alpha_before = alpha;
+        let mut best = isize::MIN;
+        let mut best_move = None;
+
+        for (i, &pos) in moves.iter().enumerate() {
+            let undo = board.apply_move(pos);
+
+            // NEGAMAX RECURSION
+            let score = -self.play_inner(depth + 1, board, -beta, -alpha);
+            board.undo_move(undo);
+
+            if score > best || best_move.is_none() {
+                best = score;
+                best_move = Some(pos);
+            }
+            if score > alpha {
+                let (line, rest) = self.pv.split_at_mut(depth + 1);
+                line[depth].clear();
+                line[depth].push(pos);
+                line[depth].extend_from_slice(&rest[0]);
+            }
+            alpha = alpha.max(best);
+
+            // ✂️ BETA CUTOFF
+            if alpha >= beta {
+                self.cutoffs += 1;
+                if i == 0 {
+                    self.first_cutoffs += 1;
+                }
+                self.remember_cutoff(board, depth, pos);
+                break;
+            }
+        }
+
+        // an unfinished search says nothing about the position
+        if self.stopped {
+            return 0;
+        }
+
+        let bound = if best <= alpha_before {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.put(Entry {
+            hash,
+            depth: remaining as u8,
+            score: to_table(best, ply),
+            bound,
+            best: best_move,
+        });
+
+        best
+    }
+
+    /// Sorts `moves` so the ones most likely to cause a cutoff come first: the best move the
+    /// table remembers, then moves that win a sub-board, moves that stop the opponent winning
+    /// one, the killer moves of this depth, and the rest by their history.
+    fn order(
+        &self,
+        moves: &mut [(usize, usize)],
+        board: &Board,
+        depth: usize,
+        tt_move: Option<(usize, usize)>,
+    ) {
+        let (me, them) = (board.turn(), board.turn().other());
+        let history = &self.history[side_index(me)];
+        let killers = &self.killers[depth];
+
+        // work out every key once, as they take a few lookups each
+        let mut keyed = [(0, (0, 0)); 81];
+        for (key, &pos) in keyed.iter_mut().zip(moves.iter()) {
+            let priority = if Some(pos) == tt_move {
+                5
+            } else if board.completes_line(pos, me) {
+                4
+            } else if board.completes_line(pos, them) {
+                3
+            } else if killers[0] == Some(pos) {
+                2
+            } else if killers[1] == Some(pos) {
+                1
+            } else {
+                0
+            };
+            *key = ((priority << 32) | history[pos.0 * 9 + pos.1] as u64, pos);
+        }
+
+        let keyed = &mut keyed[..moves.len()];
+        keyed.sort_unstable_by_key(|(key, _)| std::cmp::Reverse(*key));
+        for (pos, (_, sorted)) in moves.iter_mut().zip(keyed.iter()) {
+            *pos = *sorted;
+        }
+    }
+
+    /// Remembers a quiet move that caused a cutoff as a killer at `depth` and in the history.
+    /// Moves that win or save a sub-board are tried early anyway.
+    fn remember_cutoff(&mut self, board: &Board, depth: usize, pos: (usize, usize)) {
+        let me = board.turn();
+        if board.completes_line(pos, me) || board.completes_line(pos, me.other()) {
+            return;
+        }
+
+        let killers = &mut self.killers[depth];
+        if killers[0] != Some(pos) {
+            killers[1] = killers[0];
+            killers[0] = Some(pos);
+        }
+
+        let remaining = (self.max_depth - depth) as u32;
+        let history = &mut self.history[side_index(me)][pos.0 * 9 + pos.1];
+        *history = history.saturating_add(remaining * remaining);
+    }
+}
+
+fn side_index(piece: Piece) -> usize {
+    if piece == Piece::O { 1 } else { 0 }
+}
+
+/// What a search found out about a position.
+pub struct Analysis {
+    /// Every legal move with its score, best first, from the deepest search that finished. Empty
+    /// if not even the shallowest search finished.
+    pub scores: Vec<((usize, usize), isize)>,
+    /// The moves both sides are expected to play, starting with the best one.
+    pub pv: Vec<(usize, usize)>,
+    /// How many moves ahead the deepest search that finished looked.
+    pub depth: usize,
+    /// The positions searched, by every thread together for `Minimax::analyze`.
+    pub nodes: u64,
+    /// The positions where a move was good enough to skip the rest, and how many times that was
+    /// the first move tried, counted by the first thread.
+    pub cutoffs: u64,
+    pub first_cutoffs: u64,
+    pub elapsed: Duration,
+}
+
+impl Analysis {
+    /// The moves that share the best score.
+    pub fn bests(&self) -> Vec<(usize, usize)> {
+        self.scores
+            .iter()
+            .take_while(|(_, score)| *score == self.scores[0].1)
+            .map(|(pos, _)| *pos)
+            .collect()
+    }
+
+    pub fn nodes_per_second(&self) -> f64 {
+        self.nodes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// How many moves per position the search looked at on average, as if the tree had the same
+    /// number of moves everywhere. Better move ordering brings it down. It counts the positions of
+    /// every thread, so only compare it between searches with the same number of threads.
+    pub fn branching_factor(&self) -> f64 {
+        (self.nodes as f64).powf(1.0 / self.depth.max(1) as f64)
+    }
+
+    /// The share of cutoffs made by the first move tried.
+    pub fn first_cutoff_rate(&self) -> f64 {
+        self.first_cutoffs as f64 / self.cutoffs.max(1) as f64
+    }
+}
+
+impl Minimax {
+    /// Searches one move deeper at a time, keeping the scores of the last search that finished,
+    /// until the budget runs out or a deeper search can't change anything.
+    ///
+    /// Thread 0 is the one whose scores are used. The helper threads search the root moves in a
+    /// shuffled order and half of them one move deeper, so that they fill the table with different
+    /// positions, and they stop once thread 0 is done. No thread looks further ahead than the level
+    /// allows, or thread 0 would find the deeper scores in the table.
+    fn deepen(
+        &self,
+        mut board: Board,
+        thread: usize,
+        start: Instant,
+        finished: &AtomicBool,
+        progress: &Progress,
+    ) -> Analysis {
+        let mut moves = board.moves();
+        let mut rng = rand::rng();
+        let helper = thread > 0;
+        if helper {
+            moves.shuffle(&mut rng);
+        }
+
+        let mut search = Search {
+            table: &self.table,
+            weights: &self.weights,
+            finished,
+            max_depth: (thread % 2).min(self.level.depth.map_or(usize::MAX, |depth| depth - 1)),
+            deadline: self.budget.time.map(|time| start + time),
+            node_limit: self.budget.nodes.filter(|_| !helper),
+            nodes: 0,
+            stopped: false,
+            horizon: false,
+            pv: Vec::new(),
+            killers: Vec::new(),
+            history: [[0; 81]; 2],
+            cutoffs: 0,
+            first_cutoffs: 0,
+        };
+
+        let (mut scores, mut pv, mut depth) = (Vec::new(), Vec::new(), 0);
+        loop {
+            let mut depth_scores = Vec::new();
+            let mut depth_pv = Vec::new();
+            search.horizon = false;
+
+            for pos in moves.iter() {
+                let undo = board.apply_move(*pos);
+                let mut score = -search.play_inner(0, &mut board, -isize::MAX, isize::MAX);
+                board.undo_move(undo);
+                if self.level.noise > 0 {
+                    score += rng.random_range(-self.level.noise..=self.level.noise) as isize;
+                }
+
+                if depth_scores.iter().all(|(_, best)| score > *best) {
+                    depth_pv = vec![*pos];
+                    depth_pv.extend_from_slice(&search.pv[0]);
+                }
+                depth_scores.push((*pos, score));
+            }
+
+            if search.stopped {
+                break;
+            }
+            scores = depth_scores;
+            pv = depth_pv;
+            depth = search.max_depth + 1;
+            if !helper {
+                progress.depth.store(depth, Ordering::Relaxed);
+            }
+
+            // a deeper search takes several times as long, so don't start one that can't finish
+            if !search.horizon
+                || self
+                    .level
+                    .depth
+                    .is_some_and(|depth| search.max_depth + 1 >= depth)
+                || (!helper
+                    && self
+                        .budget
+                        .time
+                        .is_some_and(|time| start.elapsed() * 2 >= time))
+            {
+                break;
+            }
+            search.max_depth += 1;
+        }
+
+        progress.nodes.fetch_add(search.nodes, Ordering::Relaxed);
+        scores.sort_by(|(_, a), (_, b)| b.cmp(a));
+        Analysis {
+            scores,
+            pv,
+            depth,
+            nodes: search.nodes,
+            cutoffs: search.cutoffs,
+            first_cutoffs: search.first_cutoffs,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Searches `board` on every thread of the budget, showing how deep the search has got while it
+    /// runs if `show_progress` is set.
+    pub fn analyze(&self, board: &Board, show_progress: bool) -> Analysis {
+        let start = Instant::now();
+        let finished = AtomicBool::new(false);
+        let progress = Progress::default();
+
+        // the threads do the searching, so this one is free to show how far they have got
+        let (finished, progress) = (&finished, &progress);
+        let mut analysis = thread::scope(|scope| {
+            for thread in 1..self.budget.threads {
+                scope.spawn(move || self.deepen(*board, thread, start, finished, progress));
+            }
+            let (done, result) = mpsc::channel();
+            scope.spawn(move || {
+                let result = self.deepen(*board, 0, start, finished, progress);
+                finished.store(true, Ordering::Relaxed);
+                let _ = done.send(result);
+            });
+
+            loop {
+                if show_progress {
+                    print!(
+                        "\rThinking... depth {}",
+                        progress.depth.load(Ordering::Relaxed)
+                    );
+                    let _ = io::stdout().flush();
+                }
+                match result.recv_timeout(PROGRESS_INTERVAL) {
+                    Ok(result) => {
+                        if show_progress {
+                            println!(
+                                "\rThinking... depth {}",
+                                progress.depth.load(Ordering::Relaxed)
+                            );
+                        }
+                        break result;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => panic!("The search thread panicked"),
+                }
+            }
+        });
+
+        // every thread has finished by now, so this counts the positions they all searched
+        analysis.nodes = progress.nodes.load(Ordering::Relaxed);
+        analysis
+    }
+
+    /// The move to play, picked at random from the best ones, or from all of them when the level
+    /// calls for a mistake.
+    pub fn pick_move(&self, board: &Board, show_progress: bool) -> Option<(usize, usize)> {
+        let moves = board.moves();
+        if moves.is_empty() {
+            return None;
+        }
+        if rand::random_bool(self.level.mistakes) {
+            let rand: u32 = rand::random();
+            return Some(moves[rand as usize % moves.len()]);
+        }
+
+        let mut bests = self.analyze(board, show_progress).bests();
+        if bests.is_empty() {
+            let rand: u32 = rand::random();
+            let rand = rand as usize % moves.len();
+            bests.push(moves[rand]);
+        }
+
+        let rand: u32 = rand::random();
+        let rand = rand as usize % bests.len();
+
+        Some(bests[rand])
+    }
+}
+
+/// How far the search threads have got, for `Minimax::analyze` to show.
+#[derive(Default)]
+struct Progress {
+    depth: AtomicUsize,
+    nodes: AtomicU64,
+}
+
+/// Turns a score found `ply` moves from the root into one that counts the moves to the end of the
+/// game from the position itself, so that the table stays right wherever the position is reached
+/// again.
+fn to_table(score: isize, ply: isize) -> isize {
+    if score > WIN_SCORE - MAX_PLIES {
+        score + ply
+    } else if score < -(WIN_SCORE - MAX_PLIES) {
+        score - ply
+    } else {
+        score
+    }
+}
+
+/// Undoes `to_table` for a position `ply` moves from the root.
+fn from_table(score: isize, ply: isize) -> isize {
+    if score > WIN_SCORE - MAX_PLIES {
+        score - ply
+    } else if score < -(WIN_SCORE - MAX_PLIES) {
+        score + ply
+    } else {
+        score
+    }
+}
+
+impl PlayerTrait for Minimax {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        let board = Board::new(game, *turn, active);
+        self.pick_move(&board, true).map(Action::Move)
+    }
+
+    fn play_quietly(
+        &self,
+        game: &Game,
+        turn: &Piece,
+        active: Option<(usize, usize)>,
+    ) -> Option<Action> {
+        let board = Board::new(game, *turn, active);
+        self.pick_move(&board, false).map(Action::Move)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A finished sub-board that nobody won.
+    const DRAWN: [&str; 3] = ["xox", "xoo", "oxx"];
+
+    /// A position where `ahead` has won the top left and top middle sub-boards, every sub-board
+    /// below them is drawn and the top right one (`g1` to `i3`) is the active one, so whoever
+    /// wins it wins the game. `top_right` holds its rows, with `a` for `ahead`, `b` for the other
+    /// side and `.` for an empty cell.
+    #[allow(clippy::needless_range_loop)]
+    fn position(ahead: Piece, turn: Piece, top_right: [&str; 3]) -> Board {
+        let piece = |c| match c {
+            'a' => ahead,
+            'b' => ahead.other(),
+            'x' => Piece::X,
+            'o' => Piece::O,
+            _ => Piece::None,
+        };
+
+        let mut game = [[[[Piece::None; 3]; 3]; 3]; 3];
+        for x1 in 0..3 {
+            for y1 in 0..3 {
+                let rows = match (x1, y1) {
+                    (0, 0) | (1, 0) => ["aaa", "...", "..."],
+                    (2, 0) => top_right,
+                    _ => DRAWN,
+                };
+                for (y0, row) in rows.iter().enumerate() {
+                    for (x0, c) in row.chars().enumerate() {
+                        game[x1][y1][x0][y0] = piece(c);
+                    }
+                }
+            }
+        }
+
+        Board::new(&game, turn, Some((2, 0)))
+    }
+
+    fn analyze(board: &Board) -> Analysis {
+        let budget = Budget {
+            time: None,
+            nodes: Some(1_000_000),
+            threads: 1,
+        };
+        Minimax::new(budget, Level::EXPERT, Weights::default()).analyze(board, false)
+    }
+
+    fn pos(pos: &str) -> (usize, usize) {
+        crate::string_as_pos(pos).unwrap()
+    }
+
+    #[test]
+    fn wins_in_one() {
+        for side in [Piece::X, Piece::O] {
+            let analysis = analyze(&position(side, side, ["aa.", "...", "b.b"]));
+            assert_eq!(analysis.bests(), [pos("i1")]);
+            assert_eq!(analysis.scores[0].1, WIN_SCORE - 1);
+        }
+    }
+
+    #[test]
+    fn wins_in_two() {
+        for side in [Piece::X, Piece::O] {
+            // g2 and g3 both threaten two ways to win the sub-board
+            let analysis = analyze(&position(side, side, ["ab.", ".a.", "..b"]));
+            assert_eq!(analysis.scores[0].1, WIN_SCORE - 3);
+            for best in analysis.bests() {
+                assert!([pos("g2"), pos("g3")].contains(&best));
+            }
+        }
+    }
+
+    #[test]
+    fn wins_in_three() {
+        for side in [Piece::X, Piece::O] {
+            // a corner against an edge next to it wins at tic-tac-toe
+            let analysis = analyze(&position(side, side, ["ab.", "...", "..."]));
+            assert_eq!(analysis.scores[0].1, WIN_SCORE - 5);
+            assert_eq!(analysis.pv.len(), 5);
+        }
+    }
+
+    #[test]
+    fn blocks_a_win() {
+        for side in [Piece::X, Piece::O] {
+            // any move but i1 lets the other side play i1 anywhere, while i1 holds the draw
+            let analysis = analyze(&position(side.other(), side, ["aa.", ".b.", "..."]));
+            assert_eq!(analysis.bests(), [pos("i1")]);
+            assert_eq!(analysis.scores[0].1, 0);
+            assert!(
+                analysis.scores[1..]
+                    .iter()
+                    .all(|(_, score)| *score == -(WIN_SCORE - 2))
+            );
+        }
+    }
+}
@@ -0,0 +1,1216 @@
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Action, Game, GameState, Human, MAX_CHAT_LEN, Piece, Player, PlayerTrait, StateError,
+    auth::{self, Key, NONCE_LEN, Nonce, PROOF_LEN, TAG_LEN},
+    check_move,
+    lobby::{Listing, Status},
+    pieces_placed, pos_as_string, print_result,
+    server::{HANDSHAKE_TIMEOUT, RECONNECT_INTERVAL, RejoinCode},
+};
+
+pub const PORT: u16 = 2003;
+pub const PROTOCOL_VERSION: u8 = 6;
+
+/// Every this many moves, the player who just moved also sends a full snapshot of the board, and
+/// the game ends if it doesn't match the other side's.
+const RESYNC_INTERVAL: u8 = 8;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often a peer waiting to be called back checks for a connection.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+pub const MAX_MESSAGE_LEN: usize = 1024;
+/// The longest name a lobby member may have, in characters.
+pub const MAX_NAME_LEN: usize = 20;
+/// The longest name an Enter or Invite message may carry, in bytes, which fits `MAX_NAME_LEN`
+/// characters of any script.
+const MAX_NAME_BYTES: usize = MAX_NAME_LEN * 4;
+
+const MSG_HELLO: u8 = b'H';
+const MSG_WELCOME: u8 = b'W';
+const MSG_REJECT: u8 = b'J';
+const MSG_MOVE: u8 = b'M';
+const MSG_ACK: u8 = b'A';
+const MSG_STATE: u8 = b'S';
+const MSG_GAME_OVER: u8 = b'G';
+const MSG_RESIGN: u8 = b'R';
+const MSG_BYE: u8 = b'B';
+const MSG_ILLEGAL: u8 = b'I';
+const MSG_NOTICE: u8 = b'N';
+const MSG_WATCH: u8 = b'V';
+const MSG_JOINED: u8 = b'T';
+const MSG_REJOIN: u8 = b'Y';
+const MSG_RESUME: u8 = b'U';
+const MSG_CHAT: u8 = b'C';
+const MSG_CHALLENGE: u8 = b'E';
+const MSG_PROOF: u8 = b'F';
+const MSG_ENTER: u8 = b'L';
+const MSG_SEEK: u8 = b'K';
+const MSG_INVITE: u8 = b'X';
+const MSG_PLAYERS: u8 = b'P';
+
+/// What the connecting side wants to do, sent in its `Hello`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Role {
+    /// One of two equal players that both keep track of the game (`tut3 <ip> local remote`).
+    Peer,
+    /// A player in a game refereed by a server (`tut3 connect`).
+    Player,
+    /// Someone who only watches a game (`tut3 watch`).
+    Spectator,
+    /// A player coming back to a game they lost the connection to, either on a server or with a
+    /// peer.
+    Returning,
+    /// A player looking for an opponent in a lobby (`tut3 lobby <address>`).
+    Lobby,
+}
+
+impl Role {
+    fn as_u8(&self) -> u8 {
+        match self {
+            Role::Peer => 0,
+            Role::Player => 1,
+            Role::Spectator => 2,
+            Role::Returning => 3,
+            Role::Lobby => 4,
+        }
+    }
+
+    fn from_u8(u8: u8) -> Option<Role> {
+        match u8 {
+            0 => Some(Role::Peer),
+            1 => Some(Role::Player),
+            2 => Some(Role::Spectator),
+            3 => Some(Role::Returning),
+            4 => Some(Role::Lobby),
+            _ => None,
+        }
+    }
+}
+
+/// A message sent between two tut3 instances. On the wire every message is a big-endian `u16`
+/// length followed by that many bytes: a kind byte and the payload.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Message {
+    /// The first message from the connecting side, with the piece it would like to play. It
+    /// carries a nonce if the connecting side wants to use a password.
+    Hello {
+        version: u8,
+        role: Role,
+        side: Piece,
+        nonce: Option<Nonce>,
+    },
+    /// The hosting side's answer to `Hello`, with the piece the connecting side will play.
+    Welcome {
+        side: Piece,
+    },
+    Reject(String),
+    /// `seq` is the number of pieces on the board before the move.
+    Move {
+        seq: u8,
+        pos: (usize, usize),
+    },
+    Ack {
+        seq: u8,
+    },
+    /// A snapshot made by `GameState::update_to_bytes`.
+    State(Vec<u8>),
+    GameOver {
+        winner: Piece,
+    },
+    Resign,
+    Bye,
+    /// A server's answer to a move that breaks the rules, or a request it cannot grant, with the
+    /// reason.
+    Illegal(String),
+    /// Something a server wants its players to know, such as the opponent leaving.
+    Notice(String),
+    /// Sent by a spectator after its `Hello` to pick a game, where 0 means the newest one.
+    Watch {
+        game: u32,
+    },
+    /// Tells a player which game they are in and the secret token that lets them rejoin it for
+    /// `grace` seconds after losing the connection. The host of a peer game sends it to the other
+    /// side when the game starts.
+    Joined {
+        game: u32,
+        token: u64,
+        grace: u16,
+    },
+    /// Sent by a returning player after its `Hello`.
+    Rejoin {
+        game: u32,
+        token: u64,
+    },
+    /// The moves played so far, sent to a player who rejoined before the board itself.
+    Resume {
+        moves: Vec<(usize, usize)>,
+    },
+    /// Something a player said. Control characters are dropped when it is received, so that a
+    /// message cannot mess with the terminal.
+    Chat {
+        from: Piece,
+        text: String,
+    },
+    /// The host's answer to a `Hello` with a nonce, see `auth::Key`.
+    Challenge {
+        nonce: Nonce,
+    },
+    /// Proves that the sender knows the password. The connecting side sends one in answer to the
+    /// `Challenge`, and the host answers that with its own.
+    Proof([u8; PROOF_LEN]),
+    /// Sent by a lobby player after its `Hello`, with the name to show to the others.
+    Enter {
+        name: String,
+    },
+    /// Asks the lobby for a game against anyone else who asks for one.
+    Seek,
+    /// Asks the lobby for a game against the named player, which starts once they ask for one
+    /// against the sender too.
+    Invite {
+        name: String,
+    },
+    /// The lobby's list of players, sent whenever it changes.
+    Players(Vec<Listing>),
+}
+
+impl Message {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Message::Hello {
+                version,
+                role,
+                side,
+                nonce,
+            } => {
+                bytes.extend([MSG_HELLO, *version, role.as_u8(), side.as_u8()]);
+                bytes.extend(nonce.iter().flatten());
+            }
+            Message::Welcome { side } => bytes.extend([MSG_WELCOME, side.as_u8()]),
+            Message::Reject(reason) => {
+                bytes.push(MSG_REJECT);
+                bytes.extend(reason.as_bytes());
+            }
+            Message::Move { seq, pos } => bytes.extend([MSG_MOVE, *seq, pos.0 as u8, pos.1 as u8]),
+            Message::Ack { seq } => bytes.extend([MSG_ACK, *seq]),
+            Message::State(state) => {
+                bytes.push(MSG_STATE);
+                bytes.extend(state);
+            }
+            Message::GameOver { winner } => bytes.extend([MSG_GAME_OVER, winner.as_u8()]),
+            Message::Resign => bytes.push(MSG_RESIGN),
+            Message::Bye => bytes.push(MSG_BYE),
+            Message::Illegal(reason) => {
+                bytes.push(MSG_ILLEGAL);
+                bytes.extend(reason.as_bytes());
+            }
+            Message::Notice(text) => {
+                bytes.push(MSG_NOTICE);
+                bytes.extend(text.as_bytes());
+            }
+            Message::Watch { game } => {
+                bytes.push(MSG_WATCH);
+                bytes.extend(game.to_be_bytes());
+            }
+            Message::Joined { game, token, grace } => {
+                bytes.push(MSG_JOINED);
+                bytes.extend(game.to_be_bytes());
+                bytes.extend(token.to_be_bytes());
+                bytes.extend(grace.to_be_bytes());
+            }
+            Message::Rejoin { game, token } => {
+                bytes.push(MSG_REJOIN);
+                bytes.extend(game.to_be_bytes());
+                bytes.extend(token.to_be_bytes());
+            }
+            Message::Resume { moves } => {
+                bytes.push(MSG_RESUME);
+                for (x, y) in moves {
+                    bytes.extend([*x as u8, *y as u8]);
+                }
+            }
+            Message::Chat { from, text } => {
+                bytes.extend([MSG_CHAT, from.as_u8()]);
+                bytes.extend(text.as_bytes());
+            }
+            Message::Challenge { nonce } => {
+                bytes.push(MSG_CHALLENGE);
+                bytes.extend(nonce);
+            }
+            Message::Proof(proof) => {
+                bytes.push(MSG_PROOF);
+                bytes.extend(proof);
+            }
+            Message::Enter { name } => {
+                bytes.push(MSG_ENTER);
+                bytes.extend(name.as_bytes());
+            }
+            Message::Seek => bytes.push(MSG_SEEK),
+            Message::Invite { name } => {
+                bytes.push(MSG_INVITE);
+                bytes.extend(name.as_bytes());
+            }
+            Message::Players(players) => {
+                bytes.push(MSG_PLAYERS);
+                for player in players {
+                    bytes.push(player.name.len() as u8);
+                    bytes.extend(player.name.as_bytes());
+                    bytes.push(player.status.as_u8());
+                    for count in [player.wins, player.losses, player.draws] {
+                        bytes.extend(count.to_be_bytes());
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Message, NetError> {
+        let piece = |u8: u8| Piece::from_u8(u8).ok_or(NetError::Malformed);
+
+        let message = match bytes {
+            [MSG_HELLO, version, role, side, nonce @ ..]
+                if *version == PROTOCOL_VERSION
+                    && (nonce.is_empty() || nonce.len() == NONCE_LEN) =>
+            {
+                Message::Hello {
+                    version: *version,
+                    role: Role::from_u8(*role).ok_or(NetError::Malformed)?,
+                    side: piece(*side)?,
+                    nonce: nonce.try_into().ok(),
+                }
+            }
+            // older versions are only reported, whatever their hello looks like
+            [MSG_HELLO, version, ..] if *version != PROTOCOL_VERSION => Message::Hello {
+                version: *version,
+                role: Role::Peer,
+                side: Piece::None,
+                nonce: None,
+            },
+            [MSG_WELCOME, side] => Message::Welcome {
+                side: piece(*side)?,
+            },
+            [MSG_REJECT, reason @ ..] => {
+                Message::Reject(String::from_utf8_lossy(reason).into_owned())
+            }
+            [MSG_MOVE, seq, x, y] if *x < 9 && *y < 9 => Message::Move {
+                seq: *seq,
+                pos: (*x as usize, *y as usize),
+            },
+            [MSG_ACK, seq] => Message::Ack { seq: *seq },
+            [MSG_STATE, state @ ..] => Message::State(state.to_vec()),
+            [MSG_GAME_OVER, winner] => Message::GameOver {
+                winner: piece(*winner)?,
+            },
+            [MSG_RESIGN] => Message::Resign,
+            [MSG_BYE] => Message::Bye,
+            [MSG_ILLEGAL, reason @ ..] => {
+                Message::Illegal(String::from_utf8_lossy(reason).into_owned())
+            }
+            [MSG_NOTICE, text @ ..] => Message::Notice(String::from_utf8_lossy(text).into_owned()),
+            [MSG_WATCH, game @ ..] => Message::Watch {
+                game: u32::from_be_bytes(game.try_into().map_err(|_| NetError::Malformed)?),
+            },
+            [MSG_JOINED, rest @ ..] if rest.len() == 14 => Message::Joined {
+                game: u32::from_be_bytes(rest[..4].try_into().unwrap()),
+                token: u64::from_be_bytes(rest[4..12].try_into().unwrap()),
+                grace: u16::from_be_bytes(rest[12..].try_into().unwrap()),
+            },
+            [MSG_REJOIN, rest @ ..] if rest.len() == 12 => Message::Rejoin {
+                game: u32::from_be_bytes(rest[..4].try_into().unwrap()),
+                token: u64::from_be_bytes(rest[4..].try_into().unwrap()),
+            },
+            [MSG_RESUME, moves @ ..] if moves.len() % 2 == 0 && moves.iter().all(|c| *c < 9) => {
+                Message::Resume {
+                    moves: moves
+                        .chunks(2)
+                        .map(|pos| (pos[0] as usize, pos[1] as usize))
+                        .collect(),
+                }
+            }
+            [MSG_CHAT, from, text @ ..] => Message::Chat {
+                from: piece(*from)?,
+                text: String::from_utf8_lossy(text)
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .take(MAX_CHAT_LEN)
+                    .collect(),
+            },
+            [MSG_CHALLENGE, nonce @ ..] => Message::Challenge {
+                nonce: nonce.try_into().map_err(|_| NetError::Malformed)?,
+            },
+            [MSG_PROOF, proof @ ..] => {
+                Message::Proof(proof.try_into().map_err(|_| NetError::Malformed)?)
+            }
+            [MSG_ENTER, name @ ..] if name.len() <= MAX_NAME_BYTES => Message::Enter {
+                name: String::from_utf8_lossy(name).into_owned(),
+            },
+            [MSG_SEEK] => Message::Seek,
+            [MSG_INVITE, name @ ..] if name.len() <= MAX_NAME_BYTES => Message::Invite {
+                name: String::from_utf8_lossy(name).into_owned(),
+            },
+            [MSG_PLAYERS, rest @ ..] => {
+                let mut rest = rest;
+                let mut players = Vec::new();
+                while let [len, after @ ..] = rest {
+                    let (name, after) = after
+                        .split_at_checked(*len as usize)
+                        .ok_or(NetError::Malformed)?;
+                    let [status, w1, w2, l1, l2, d1, d2, after @ ..] = after else {
+                        return Err(NetError::Malformed);
+                    };
+                    players.push(Listing {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        status: Status::from_u8(*status).ok_or(NetError::Malformed)?,
+                        wins: u16::from_be_bytes([*w1, *w2]),
+                        losses: u16::from_be_bytes([*l1, *l2]),
+                        draws: u16::from_be_bytes([*d1, *d2]),
+                    });
+                    rest = after;
+                }
+                Message::Players(players)
+            }
+            _ => return Err(NetError::Malformed),
+        };
+        Ok(message)
+    }
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    Disconnected,
+    Malformed,
+    /// The other side sent a valid message that makes no sense at this point of the game.
+    Unexpected(Message),
+    Rejected(String),
+    VersionMismatch(u8),
+    State(StateError),
+    WrongPassword,
+    /// A message did not carry the right MAC.
+    Tampered,
+    /// The other side's snapshot of the board differs from this side's.
+    OutOfSync,
+    /// A message was too long to fit in a frame, so it wasn't sent.
+    TooLong(usize),
+}
+
+impl Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetError::Io(e) => write!(f, "{e}"),
+            NetError::Disconnected => write!(f, "the other side disconnected"),
+            NetError::Malformed => write!(f, "received a malformed message"),
+            NetError::Unexpected(message) => write!(f, "received an unexpected {message:?}"),
+            NetError::Rejected(reason) => {
+                write!(f, "the other side refused the connection: {reason}")
+            }
+            NetError::VersionMismatch(version) => write!(
+                f,
+                "the other side speaks protocol version {version}, but this is version {PROTOCOL_VERSION}"
+            ),
+            NetError::State(e) => write!(f, "received an invalid game state: {e}"),
+            NetError::WrongPassword => write!(f, "the other side does not know the password"),
+            NetError::Tampered => write!(
+                f,
+                "a message failed its integrity check, so it was changed on the way or sent by someone without the password"
+            ),
+            NetError::OutOfSync => write!(f, "the two boards no longer match"),
+            NetError::TooLong(len) => write!(f, "a message of {len} bytes is too long to send"),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => NetError::Disconnected,
+            _ => NetError::Io(e),
+        }
+    }
+}
+
+/// The moves of a peer game and a snapshot of its board.
+type History = (Vec<(usize, usize)>, Vec<u8>);
+
+/// A TCP connection to another tut3 instance, after the handshake has settled who plays which side.
+pub struct Session {
+    stream: TcpStream,
+    /// The piece played from this machine.
+    pub side: Piece,
+    /// Chat messages from the other side that the board has not shown yet.
+    chat: Vec<(Piece, String)>,
+    /// Set once the handshake has checked the password.
+    seal: Option<Seal>,
+    /// Whether this side accepted the connection.
+    pub hosting: bool,
+    /// The moves of a peer game so far, for when the other side comes back.
+    moves: Vec<(usize, usize)>,
+}
+
+/// The MACs of the messages on a connection with a password.
+#[derive(Clone)]
+struct Seal {
+    key: Key,
+    /// Whether this side is the one that accepted the connection.
+    host: bool,
+    sent: u64,
+    received: u64,
+}
+
+impl Session {
+    /// Connects to a game hosted at `peer`, or hosts one and waits for `peer` if nobody is
+    /// listening there yet. `side` is the piece this machine would like to play.
+    pub fn connect_or_host(
+        peer: IpAddr,
+        side: Piece,
+        key: Option<&Key>,
+    ) -> Result<Session, NetError> {
+        match TcpStream::connect_timeout(&SocketAddr::new(peer, PORT), CONNECT_TIMEOUT) {
+            Ok(stream) => Session::join(stream, Role::Peer, side, key),
+            Err(_) => {
+                let listener = TcpListener::bind(("0.0.0.0", PORT))?;
+                println!("Waiting for {peer} to connect...");
+                loop {
+                    let (stream, from) = listener.accept()?;
+                    if from.ip() == peer {
+                        return Session::host(stream, side, key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Performs the handshake as the connecting side, which plays `side` unless the host has
+    /// already claimed it. With a `key`, the host has to use the same password.
+    pub fn join(
+        stream: TcpStream,
+        role: Role,
+        side: Piece,
+        key: Option<&Key>,
+    ) -> Result<Session, NetError> {
+        Session::join_with(stream, role, side, key, |_| Ok(()))
+    }
+
+    /// Like `join`, but lets `after_hello` send more messages before waiting for the `Welcome`.
+    pub fn join_with(
+        stream: TcpStream,
+        role: Role,
+        side: Piece,
+        key: Option<&Key>,
+        after_hello: impl FnOnce(&mut Session) -> Result<(), NetError>,
+    ) -> Result<Session, NetError> {
+        let mut session = Session::new(stream);
+        let nonce = key.map(|_| auth::nonce());
+        session.send(&Message::Hello {
+            version: PROTOCOL_VERSION,
+            role,
+            side,
+            nonce,
+        })?;
+
+        if let (Some(key), Some(ours)) = (key, nonce) {
+            let theirs = match session.recv()? {
+                Message::Challenge { nonce } => nonce,
+                Message::Reject(reason) => return Err(NetError::Rejected(reason)),
+                other => return Err(NetError::Unexpected(other)),
+            };
+            session.send(&Message::Proof(key.proof(auth::JOINING, &ours, &theirs)))?;
+            match session.recv()? {
+                Message::Proof(proof) if key.check_proof(auth::HOST, &ours, &theirs, &proof) => {}
+                Message::Proof(_) => return Err(NetError::WrongPassword),
+                Message::Reject(reason) => return Err(NetError::Rejected(reason)),
+                other => return Err(NetError::Unexpected(other)),
+            }
+            session.seal(key.session(&ours, &theirs), false);
+        }
+        after_hello(&mut session)?;
+
+        match session.recv()? {
+            Message::Welcome { side } => {
+                session.side = side;
+                Ok(session)
+            }
+            Message::Reject(reason) => Err(NetError::Rejected(reason)),
+            other => Err(NetError::Unexpected(other)),
+        }
+    }
+
+    /// Performs the handshake as the hosting side, which always gets to play `side` when it asks
+    /// for one.
+    pub fn host(stream: TcpStream, side: Piece, key: Option<&Key>) -> Result<Session, NetError> {
+        let (mut session, role, wanted) = Session::accept(stream, key)?;
+        if role != Role::Peer {
+            let reason = "this is a game between two players, use `tut3 <ip> local remote`";
+            session.reject(reason)?;
+            return Err(NetError::Rejected(reason.to_string()));
+        }
+
+        session.side = match (side, wanted) {
+            (Piece::None, Piece::None) => Piece::X,
+            (Piece::None, wanted) => wanted.other(),
+            (side, _) => side,
+        };
+        session.send(&Message::Welcome {
+            side: session.side.other(),
+        })?;
+
+        Ok(session)
+    }
+
+    /// Reads the `Hello` of a new connection, returning what the other side would like to do. The
+    /// caller still has to answer with either `Welcome` or `Reject`. With a `key`, the other side
+    /// has to prove it knows the same password first.
+    pub fn accept(
+        stream: TcpStream,
+        key: Option<&Key>,
+    ) -> Result<(Session, Role, Piece), NetError> {
+        let mut session = Session::new(stream);
+        session.hosting = true;
+
+        let (role, side, nonce) = match session.recv()? {
+            Message::Hello {
+                version,
+                role,
+                side,
+                nonce,
+            } if version == PROTOCOL_VERSION => (role, side, nonce),
+            Message::Hello { version, .. } => {
+                session.reject(&format!("protocol version {version} is not supported"))?;
+                return Err(NetError::VersionMismatch(version));
+            }
+            other => return Err(NetError::Unexpected(other)),
+        };
+
+        match (key, nonce) {
+            (None, None) => {}
+            (Some(key), Some(theirs)) => {
+                let ours = auth::nonce();
+                session.send(&Message::Challenge { nonce: ours })?;
+                match session.recv()? {
+                    Message::Proof(proof)
+                        if key.check_proof(auth::JOINING, &theirs, &ours, &proof) => {}
+                    Message::Proof(_) => {
+                        session.reject("wrong password")?;
+                        return Err(NetError::WrongPassword);
+                    }
+                    other => return Err(NetError::Unexpected(other)),
+                }
+                session.send(&Message::Proof(key.proof(auth::HOST, &theirs, &ours)))?;
+                session.seal(key.session(&theirs, &ours), true);
+            }
+            (Some(_), None) => {
+                let reason = "this game needs a password, use `--password`";
+                session.reject(reason)?;
+                return Err(NetError::Rejected(reason.to_string()));
+            }
+            (None, Some(_)) => {
+                let reason = "this game does not use a password";
+                session.reject(reason)?;
+                return Err(NetError::Rejected(reason.to_string()));
+            }
+        }
+
+        Ok((session, role, side))
+    }
+
+    pub fn reject(&mut self, reason: &str) -> Result<(), NetError> {
+        self.send(&Message::Reject(reason.to_string()))
+    }
+
+    fn new(stream: TcpStream) -> Session {
+        // moves are tiny and latency matters more than throughput
+        let _ = stream.set_nodelay(true);
+        Session {
+            stream,
+            side: Piece::None,
+            chat: Vec::new(),
+            seal: None,
+            hosting: false,
+            moves: Vec::new(),
+        }
+    }
+
+    /// Protects every message from now on with a MAC made with `key`.
+    fn seal(&mut self, key: Key, host: bool) {
+        self.seal = Some(Seal {
+            key,
+            host,
+            sent: 0,
+            received: 0,
+        });
+    }
+
+    /// Opens a second handle to the same connection, so that one thread can read while another
+    /// one writes. Only one of the handles may read, and only one may write, or their messages
+    /// are rejected as replayed when there is a password.
+    pub fn try_clone(&self) -> Result<Session, NetError> {
+        Ok(Session {
+            stream: self.stream.try_clone()?,
+            side: self.side,
+            chat: Vec::new(),
+            seal: self.seal.clone(),
+            hosting: self.hosting,
+            moves: Vec::new(),
+        })
+    }
+
+    /// Hangs up, which also wakes up anything waiting to read from a clone of this session.
+    pub fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        let _ = self.stream.set_read_timeout(timeout);
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) {
+        let _ = self.stream.set_write_timeout(timeout);
+    }
+
+    /// Checks, without waiting, whether the other side has hung up.
+    pub fn is_closed(&self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let closed = match self.stream.peek(&mut [0u8; 1]) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+        };
+        let _ = self.stream.set_nonblocking(false);
+        closed
+    }
+
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        let mut bytes = message.to_bytes();
+        if bytes.len() > MAX_MESSAGE_LEN {
+            return Err(NetError::TooLong(bytes.len()));
+        }
+        if let Some(seal) = &mut self.seal {
+            let sender = if seal.host { auth::HOST } else { auth::JOINING };
+            bytes.extend(seal.key.tag(sender, seal.sent, &bytes));
+            seal.sent += 1;
+        }
+
+        let mut frame = Vec::with_capacity(bytes.len() + 2);
+        frame.extend((bytes.len() as u16).to_be_bytes());
+        frame.extend(bytes);
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    pub fn recv(&mut self) -> Result<Message, NetError> {
+        let mut len = [0u8; 2];
+        self.stream.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len) as usize;
+        let tag_len = if self.seal.is_some() { TAG_LEN } else { 0 };
+        if len <= tag_len || len > MAX_MESSAGE_LEN + tag_len {
+            return Err(NetError::Malformed);
+        }
+
+        let mut bytes = vec![0u8; len];
+        self.stream.read_exact(&mut bytes)?;
+
+        if let Some(seal) = &mut self.seal {
+            let (message, tag) = bytes.split_at(len - TAG_LEN);
+            let sender = if seal.host { auth::JOINING } else { auth::HOST };
+            if !seal.key.check_tag(sender, seal.received, message, tag) {
+                return Err(NetError::Tampered);
+            }
+            seal.received += 1;
+            bytes.truncate(len - TAG_LEN);
+        }
+        Message::from_bytes(&bytes)
+    }
+
+    /// Sends a move made on this machine and waits for the other side to accept it.
+    fn send_move(&mut self, state: &GameState, pos: (usize, usize)) -> Result<(), NetError> {
+        let seq = pieces_placed(&state.game);
+        self.moves.push(pos);
+        self.send(&Message::Move { seq, pos })?;
+
+        if (seq + 1).is_multiple_of(RESYNC_INTERVAL) {
+            let mut after = state.clone();
+            after.manual_turn(pos.0, pos.1);
+            self.send(&Message::State(after.update_to_bytes()))?;
+        }
+
+        match self.recv()? {
+            Message::Ack { seq: acked } if acked == seq => Ok(()),
+            Message::Bye => Err(NetError::Disconnected),
+            other => Err(NetError::Unexpected(other)),
+        }
+    }
+
+    /// Waits for the other side's move and checks it against the local board.
+    fn recv_move(&mut self, state: &GameState) -> Result<Action, NetError> {
+        let seq = pieces_placed(&state.game);
+
+        let pos = loop {
+            match self.recv()? {
+                Message::Move { seq: got, pos } if got == seq => break pos,
+                Message::Chat { from, text } => {
+                    println!("{from}: {text}");
+                    self.chat.push((from, text));
+                }
+                Message::Resign => return Ok(Action::Resign),
+                Message::Bye => return Err(NetError::Disconnected),
+                other => return Err(NetError::Unexpected(other)),
+            }
+        };
+
+        if let Err(reason) = check_move(&state.game, &state.active, pos) {
+            let reason = format!("{} is an invalid move: {reason}", pos_as_string(&pos));
+            self.send(&Message::Reject(reason.clone()))?;
+            return Err(NetError::Rejected(reason));
+        }
+
+        if (seq + 1).is_multiple_of(RESYNC_INTERVAL) {
+            let Message::State(theirs) = self.recv()? else {
+                return Err(NetError::Malformed);
+            };
+
+            let mut after = state.clone();
+            after.manual_turn(pos.0, pos.1);
+            if after.update_to_bytes() != theirs {
+                self.send(&Message::Reject("the boards no longer match".into()))?;
+                return Err(NetError::OutOfSync);
+            }
+        }
+
+        self.moves.push(pos);
+        self.send(&Message::Ack { seq })?;
+        Ok(Action::Move(pos))
+    }
+
+    /// Sends the moves and board of this side to a peer that has just come back, and returns
+    /// theirs.
+    fn swap_history(&mut self, state: &GameState) -> Result<History, NetError> {
+        self.send(&Message::Resume {
+            moves: self.moves.clone(),
+        })?;
+        self.send(&Message::State(state.update_to_bytes()))?;
+
+        let moves = match self.recv()? {
+            Message::Resume { moves } => moves,
+            other => return Err(NetError::Unexpected(other)),
+        };
+        match self.recv()? {
+            Message::State(snapshot) => Ok((moves, snapshot)),
+            other => Err(NetError::Unexpected(other)),
+        }
+    }
+
+    /// Swaps histories with a peer that has just come back while this side waits for its move,
+    /// and returns that move if the other side made it but it never arrived here.
+    fn catch_up(&mut self, state: &GameState) -> Result<Option<Action>, NetError> {
+        let (theirs, snapshot) = self.swap_history(state)?;
+        let ours = &self.moves;
+
+        if theirs.len() == ours.len() + 1 && theirs.starts_with(ours) {
+            let pos = theirs[ours.len()];
+            if let Err(reason) = check_move(&state.game, &state.active, pos) {
+                let reason = format!("{} is an invalid move: {reason}", pos_as_string(&pos));
+                return Err(NetError::Rejected(reason));
+            }
+
+            let mut after = state.clone();
+            after.manual_turn(pos.0, pos.1);
+            if after.update_to_bytes() != snapshot {
+                return Err(NetError::Unexpected(Message::State(snapshot)));
+            }
+            self.moves.push(pos);
+            return Ok(Some(Action::Move(pos)));
+        }
+
+        // otherwise the other side is either up to date, about to pick up a move of ours that
+        // never arrived there, or starting over from our board after tut3 was restarted
+        if ours.starts_with(&theirs) {
+            Ok(None)
+        } else {
+            Err(NetError::Unexpected(Message::Resume { moves: theirs }))
+        }
+    }
+
+    /// Tells the other side how the game ended and checks that it agrees, then says goodbye.
+    fn finish(&mut self, winner: Piece) -> Result<(), NetError> {
+        self.send(&Message::GameOver { winner })?;
+        let result = match self.recv()? {
+            Message::GameOver { winner: theirs } if theirs == winner => Ok(()),
+            Message::GameOver { winner: theirs } => {
+                Err(NetError::Unexpected(Message::GameOver { winner: theirs }))
+            }
+            other => Err(NetError::Unexpected(other)),
+        };
+        let _ = self.send(&Message::Bye);
+        result
+    }
+}
+
+/// Builds a throwaway `GameState` around a position handed to a player, so that it can be
+/// validated and serialized.
+fn state_of(game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> GameState {
+    let mut state = GameState::new(Arc::new(Human), Arc::new(Human));
+    state.game = *game;
+    state.turn = *turn;
+    state.active = active;
+    state
+}
+
+/// The player sitting at this machine in a network game. Moves chosen by `player` are forwarded to
+/// the other machine once they are known to be legal.
+pub struct Local {
+    pub player: Player,
+    pub session: Arc<Mutex<Session>>,
+}
+
+impl PlayerTrait for Local {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        let action = self.player.play(game, turn, active)?;
+
+        let result = match &action {
+            // illegal moves are handed back to `GameState::turn` so the player is asked again
+            Action::Move(pos) if check_move(game, &active, *pos).is_err() => return Some(action),
+            Action::Move(pos) => {
+                println!("Sending move {}...", pos_as_string(pos));
+                let state = state_of(game, turn, active);
+                self.session.lock().unwrap().send_move(&state, *pos)
+            }
+            Action::Resign => self.session.lock().unwrap().send(&Message::Resign),
+            Action::Say(text) => self.session.lock().unwrap().send(&Message::Chat {
+                from: *turn,
+                text: text.clone(),
+            }),
+        };
+
+        // a lost connection is noticed and reported while waiting for the other side's move
+        if let Err(e) = result
+            && !matches!(e, NetError::Disconnected)
+        {
+            panic!("Failed to send move: {e}");
+        }
+
+        Some(action)
+    }
+}
+
+/// What it takes to pick a peer game up again after the connection drops. The side that accepted
+/// the last connection waits to be called back, from any address, by whoever knows the code.
+struct Resumption {
+    code: RejoinCode,
+    grace: Duration,
+    /// The other side, which is called back by the side that called it last time.
+    peer: IpAddr,
+    key: Option<Key>,
+}
+
+impl Resumption {
+    /// Settles the rejoin code and grace period of a new peer game. The host makes them up and
+    /// tells the side that joined.
+    fn agree(
+        session: &mut Session,
+        grace: Duration,
+        key: Option<Key>,
+    ) -> Result<Resumption, NetError> {
+        let peer = session.peer().ok_or(NetError::Disconnected)?.ip();
+        if session.hosting {
+            let code = RejoinCode {
+                game: rand::random(),
+                token: rand::random(),
+            };
+            session.send(&Message::Joined {
+                game: code.game,
+                token: code.token,
+                grace: grace.as_secs() as u16,
+            })?;
+            Ok(Resumption {
+                code,
+                grace,
+                peer,
+                key,
+            })
+        } else {
+            match session.recv()? {
+                Message::Joined { game, token, grace } => Ok(Resumption {
+                    code: RejoinCode { game, token },
+                    grace: Duration::from_secs(grace as u64),
+                    peer,
+                    key,
+                }),
+                other => Err(NetError::Unexpected(other)),
+            }
+        }
+    }
+
+    /// Connects to the other side again before the grace period runs out, by waiting for it to
+    /// call if `listen` and by calling it if `call`. `side` is the piece played from here, or
+    /// `Piece::None` if tut3 was restarted and doesn't know it yet.
+    fn reconnect(&self, side: Piece, listen: bool, call: bool) -> Option<Session> {
+        let deadline = Instant::now() + self.grace;
+        while Instant::now() < deadline {
+            if call {
+                match self.call(side) {
+                    Ok(session) => return Some(session),
+                    Err(NetError::Rejected(reason)) => {
+                        println!("Could not rejoin: {reason}");
+                        return None;
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            // the port is only held while listening, so that a side that also calls doesn't
+            // reach itself, and so that two sides on one computer can take turns with it
+            let listener = listen
+                .then(|| TcpListener::bind(("0.0.0.0", PORT)).ok())
+                .flatten();
+            match listener {
+                Some(listener) => {
+                    if let Some(session) = self.answer(&listener, side) {
+                        return Some(session);
+                    }
+                }
+                None => thread::sleep(RECONNECT_INTERVAL),
+            }
+        }
+        None
+    }
+
+    fn call(&self, side: Piece) -> Result<Session, NetError> {
+        let stream =
+            TcpStream::connect_timeout(&SocketAddr::new(self.peer, PORT), CONNECT_TIMEOUT)?;
+        Session::join_with(
+            stream,
+            Role::Returning,
+            side,
+            self.key.as_ref(),
+            |session| {
+                session.send(&Message::Rejoin {
+                    game: self.code.game,
+                    token: self.code.token,
+                })
+            },
+        )
+    }
+
+    /// Waits a moment for the other side to call back, returning the connection if it does.
+    fn answer(&self, listener: &TcpListener, side: Piece) -> Option<Session> {
+        let _ = listener.set_nonblocking(true);
+        let until = Instant::now() + RECONNECT_INTERVAL;
+        while Instant::now() < until {
+            let Ok((stream, from)) = listener.accept() else {
+                thread::sleep(ACCEPT_POLL);
+                continue;
+            };
+
+            let _ = stream.set_nonblocking(false);
+            let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+            match self.welcome_back(stream, side) {
+                Ok(session) => {
+                    session.set_timeout(None);
+                    return Some(session);
+                }
+                Err(e) => println!("{} could not rejoin: {e}", from.ip()),
+            }
+        }
+        None
+    }
+
+    /// Checks the code of a side calling back and tells it which piece it plays.
+    fn welcome_back(&self, stream: TcpStream, side: Piece) -> Result<Session, NetError> {
+        let (mut session, role, wanted) = Session::accept(stream, self.key.as_ref())?;
+        let reason = match (role, session.recv()?) {
+            (Role::Returning, Message::Rejoin { game, token })
+                if game == self.code.game && token == self.code.token =>
+            {
+                session.side = if side == Piece::None {
+                    wanted.other()
+                } else {
+                    side
+                };
+                if session.side != Piece::None {
+                    session.send(&Message::Welcome {
+                        side: session.side.other(),
+                    })?;
+                    return Ok(session);
+                }
+                "neither side knows who plays which piece"
+            }
+            (Role::Returning, _) => "that rejoin code is wrong",
+            _ => "this game has already started, use `--rejoin`",
+        };
+        session.reject(reason)?;
+        Err(NetError::Rejected(reason.to_string()))
+    }
+}
+
+/// The player on the other machine in a network game. If the connection drops while waiting for
+/// its move, it has the grace period to come back before forfeiting.
+pub struct Remote {
+    session: Arc<Mutex<Session>>,
+    resumption: Resumption,
+}
+
+impl PlayerTrait for Remote {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        println!("Waiting for {turn} to move...");
+        let state = state_of(game, turn, active);
+        let mut session = self.session.lock().unwrap();
+
+        loop {
+            match session.recv_move(&state) {
+                Ok(action) => return Some(action),
+                Err(NetError::Disconnected) => {}
+                Err(e) => panic!("Lost sync with the other player: {e}"),
+            }
+
+            println!(
+                "Lost the connection to {turn}, waiting {} seconds for it to come back...",
+                self.resumption.grace.as_secs()
+            );
+            let (listen, call) = (session.hosting, !session.hosting);
+            let Some(mut rejoined) = self.resumption.reconnect(session.side, listen, call) else {
+                println!("{turn} did not come back and forfeits the game.");
+                return Some(Action::Resign);
+            };
+            rejoined.moves = std::mem::take(&mut session.moves);
+            *session = rejoined;
+
+            println!("{turn} is back.");
+            match session.catch_up(&state) {
+                Ok(Some(action)) => return Some(action),
+                Ok(None) => {}
+                Err(e) => panic!("Lost sync with the other player: {e}"),
+            }
+        }
+    }
+}
+
+/// Plays a whole game over `session`, with `player` making the moves on this machine. If the
+/// connection drops, the side that is away has `grace` to come back, or the host's grace period
+/// if this side joined. `key` is the password the game was started with.
+pub fn play(mut session: Session, player: Player, grace: Duration, key: Option<Key>) {
+    let resumption = Resumption::agree(&mut session, grace, key)
+        .unwrap_or_else(|e| panic!("Failed to start the game: {e}"));
+    play_on(session, player, resumption, None);
+}
+
+/// Carries on a peer game with `peer` after tut3 was closed, using the rejoin code it showed.
+/// Gives up if the other side doesn't answer within `grace`.
+pub fn rejoin(peer: IpAddr, code: RejoinCode, player: Player, grace: Duration, key: Option<Key>) {
+    let resumption = Resumption {
+        code,
+        grace,
+        peer,
+        key,
+    };
+
+    println!("Trying to rejoin game {} with {peer}...", code.game);
+    // this side may have hosted the game or joined it, so it both calls and waits to be called
+    let Some(mut session) = resumption.reconnect(Piece::None, true, true) else {
+        println!("Could not rejoin the game.");
+        return;
+    };
+    let fresh = GameState::new(Arc::new(Human), Arc::new(Human));
+    let (moves, snapshot) = session
+        .swap_history(&fresh)
+        .unwrap_or_else(|e| panic!("Failed to rejoin the game: {e}"));
+
+    let played = moves.iter().map(pos_as_string).collect::<Vec<_>>();
+    println!("Rejoined the game. Moves so far: {}", played.join(" "));
+    session.moves = moves;
+    play_on(session, player, resumption, Some(snapshot));
+}
+
+/// Plays the rest of a peer game, starting from `snapshot` if given.
+fn play_on(session: Session, player: Player, resumption: Resumption, snapshot: Option<Vec<u8>>) {
+    let side = session.side;
+    let code = resumption.code;
+    println!("You are playing as {side}.");
+
+    let session = Arc::new(Mutex::new(session));
+    let local: Player = Arc::new(Local {
+        player,
+        session: session.clone(),
+    });
+    let remote: Player = Arc::new(Remote {
+        session: session.clone(),
+        resumption,
+    });
+
+    let mut game = if side == Piece::X {
+        GameState::new(local, remote)
+    } else {
+        GameState::new(remote, local)
+    };
+    if let Some(snapshot) = snapshot {
+        game.update_from_bytes(&snapshot)
+            .unwrap_or_else(|e| panic!("Failed to rejoin the game: {e}"));
+    }
+
+    while !game.is_complete() {
+        game.print();
+        if let Some(peer) = session.lock().unwrap().peer() {
+            let sides = if side == Piece::X {
+                "local remote"
+            } else {
+                "remote local"
+            };
+            println!(
+                "If tut3 is closed, carry on with `tut3 {} {sides} --rejoin {code}`",
+                peer.ip()
+            );
+        }
+        game.turn();
+
+        for (from, text) in std::mem::take(&mut session.lock().unwrap().chat) {
+            game.add_chat(from, &text);
+        }
+    }
+
+    game.print();
+    print_result(&game);
+
+    if game.resigned == Piece::None
+        && let Err(e) = session.lock().unwrap().finish(game.won())
+    {
+        println!("Warning: the other side disagrees about the result ({e})");
+    }
+}
+
+/// Accepts `host`, `host:port`, `ip` or `ip:port`, using the default port when none is given.
+pub fn resolve(addr: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = addr.parse() {
+        return Some(addr);
+    }
+
+    match addr.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        Err(_) => (addr, PORT).to_socket_addrs().ok()?.next(),
+    }
+}
+
+/// Applies the result announced in a `GameOver` message to a board that may not show it, because
+/// somebody resigned or left.
+pub fn record_winner(game: &mut GameState, winner: Piece) {
+    if winner != Piece::None && game.won() != winner {
+        game.resigned = winner.other();
+    }
+}
@@ -0,0 +1,100 @@
+use rand::Rng;
+
+use crate::board::Board;
+
+/// How the random bot picks its moves, and how the mcts bot plays out games. The heuristic ones
+/// still play at random, but skip moves that are obviously bad, which makes them stronger
+/// opponents to measure the other bots against and gives the mcts bot more realistic playouts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Policy {
+    /// Every legal move is equally likely.
+    Uniform,
+    /// Wins a sub-board whenever it can.
+    Greedy,
+    /// Wins a sub-board whenever it can, and otherwise avoids moves that let the opponent play
+    /// anywhere, unless they end the game.
+    Careful,
+}
+
+impl Policy {
+    pub fn from_name(name: &str) -> Option<Policy> {
+        match name.to_lowercase().as_str() {
+            "uniform" => Some(Policy::Uniform),
+            "greedy" => Some(Policy::Greedy),
+            "careful" => Some(Policy::Careful),
+            _ => None,
+        }
+    }
+
+    /// A legal move, or `None` if there are none.
+    pub fn pick(self, board: &Board, rng: &mut impl Rng) -> Option<(usize, usize)> {
+        if self == Policy::Uniform {
+            return board.random_move(rng);
+        }
+
+        let moves = board.moves();
+        if moves.is_empty() {
+            return None;
+        }
+
+        let wins = moves
+            .iter()
+            .filter(|pos| board.completes_line(**pos, board.turn()))
+            .copied()
+            .collect::<Vec<_>>();
+        if !wins.is_empty() {
+            return Some(wins[rng.random_range(0..wins.len())]);
+        }
+
+        if self == Policy::Careful {
+            // after the move the opponent is sent to a sub-board, unless it is over, and a move
+            // that ends the game in a draw doesn't give them anything either
+            let safe = moves
+                .iter()
+                .filter(|pos| {
+                    let after = board.play(**pos);
+                    after.active().is_some() || after.is_over()
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            if !safe.is_empty() {
+                return Some(safe[rng.random_range(0..safe.len())]);
+            }
+        }
+
+        Some(moves[rng.random_range(0..moves.len())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Piece;
+
+    #[test]
+    fn careful_counts_a_drawn_ending_as_safe() {
+        // every sub-board but the centre one is won, without three in a row for either side
+        let winners = [
+            [Piece::X, Piece::O, Piece::X],
+            [Piece::X, Piece::None, Piece::O],
+            [Piece::O, Piece::X, Piece::X],
+        ];
+        let mut game = [[[[Piece::None; 3]; 3]; 3]; 3];
+        for x1 in 0..3 {
+            for y1 in 0..3 {
+                game[x1][y1][0] = [winners[x1][y1]; 3];
+            }
+        }
+        // X can't win the centre, but e6 leaves nobody able to, which draws the game, while f5
+        // sends O to a finished sub-board
+        let (x, o, none) = (Piece::X, Piece::O, Piece::None);
+        game[1][1] = [[x, x, o], [o, o, none], [x, none, o]];
+        let board = Board::new(&game, Piece::X, Some((1, 1)));
+        assert_eq!(board.moves().len(), 2);
+
+        let mut rng = rand::rng();
+        for _ in 0..20 {
+            assert_eq!(Policy::Careful.pick(&board, &mut rng), Some((4, 5)));
+        }
+    }
+}
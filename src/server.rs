@@ -0,0 +1,675 @@
+use std::{
+    collections::BTreeMap,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Action, GameState, Human, Piece, Player,
+    auth::Key,
+    check_move,
+    net::{Message, NetError, Role, Session, record_winner, resolve},
+    pieces_placed, pos_as_string, print_result,
+    spectate::{self, Hub},
+};
+
+/// How long a new connection may take to say hello before the server gives up on it.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a referee checks whether an absent player has run out of time.
+const TICK: Duration = Duration::from_millis(250);
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A game being played on the server, as seen by the connections that want to find it.
+struct Entry {
+    hub: Arc<Hub>,
+    /// The secret each side needs to rejoin, X first.
+    tokens: [u64; 2],
+    events: Sender<Event>,
+}
+
+/// The games currently being played on a server, by number.
+#[derive(Default)]
+pub struct Registry {
+    games: BTreeMap<u32, Entry>,
+    last_id: u32,
+}
+
+pub type Games = Arc<Mutex<Registry>>;
+
+/// Something that happened to one of the connections of a refereed game. Connections are
+/// numbered so that a late message from a connection that has since been replaced is ignored.
+enum Event {
+    Message(u64, Message),
+    Left(u64),
+    Rejoined(Session),
+}
+
+fn seat(side: Piece) -> usize {
+    if side == Piece::X { 0 } else { 1 }
+}
+
+/// Runs a server that pairs up players as they connect and referees their games, each on its own
+/// thread. The server owns the board, so a player can only make moves it accepts. A player who
+/// loses their connection has `grace` to come back before forfeiting. With a `key`, only players
+/// and spectators who know the password can connect.
+pub fn serve(port: u16, grace: Duration, key: Option<Key>) {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .unwrap_or_else(|e| panic!("Failed to listen on port {port}: {e}"));
+    println!("Serving games on port {port}");
+
+    let waiting = Arc::new(Mutex::new(None));
+    let games = Games::default();
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (waiting, games, key) = (waiting.clone(), games.clone(), key.clone());
+        // a connection that never says hello only holds up its own thread
+        thread::spawn(move || admit(stream, &waiting, &games, grace, key.as_ref()));
+    }
+}
+
+/// Shakes hands with a new connection to a server and seats it, either in the game it is
+/// rejoining or watching, or as the `waiting` player's opponent. A player with nobody to play
+/// waits there for the next one.
+fn admit(
+    stream: TcpStream,
+    waiting: &Mutex<Option<Session>>,
+    games: &Games,
+    grace: Duration,
+    key: Option<&Key>,
+) {
+    let Ok(from) = stream.peer_addr() else {
+        return;
+    };
+
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let (mut session, role, wanted) = match Session::accept(stream, key) {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            println!("{from} could not connect: {e}");
+            return;
+        }
+    };
+
+    match role {
+        Role::Player => {}
+        Role::Spectator => return add_spectator(session, games),
+        Role::Returning => return rejoin(session, from, games),
+        Role::Peer | Role::Lobby => {
+            let _ = session.reject("this is a tut3 server, use `tut3 connect`");
+            return;
+        }
+    }
+
+    let mut waiting = waiting.lock().unwrap();
+    if waiting.as_ref().is_some_and(Session::is_closed) {
+        *waiting = None;
+    }
+
+    // the first player of a pair gets the piece they asked for, the second one the other
+    session.side = match &*waiting {
+        Some(first) => first.side.other(),
+        None if wanted == Piece::None => Piece::X,
+        None => wanted,
+    };
+    if session
+        .send(&Message::Welcome { side: session.side })
+        .is_err()
+    {
+        return;
+    }
+    session.set_timeout(None);
+    println!("{from} joined as {}", session.side);
+
+    match waiting.take() {
+        Some(first) => {
+            start_game(games, vec![first, session], grace);
+        }
+        None => {
+            let _ = session.send(&Message::Notice("Waiting for an opponent...".into()));
+            *waiting = Some(session);
+        }
+    }
+}
+
+/// Referees a new game on a thread of its own, seating whichever `players` are already connected.
+/// The others have `grace` to join with their rejoin token. Returns the game number, the tokens
+/// (X first), and the thread, which finishes with the winner.
+pub fn start_game(
+    games: &Games,
+    players: Vec<Session>,
+    grace: Duration,
+) -> (u32, [u64; 2], JoinHandle<Piece>) {
+    let hub = Arc::new(Hub::default());
+    let (events, received) = mpsc::channel();
+    let tokens = [rand::random(), rand::random()];
+
+    let id = {
+        let mut games = games.lock().unwrap();
+        games.last_id += 1;
+        let id = games.last_id;
+        games.games.insert(
+            id,
+            Entry {
+                hub: hub.clone(),
+                tokens,
+                events: events.clone(),
+            },
+        );
+        id
+    };
+
+    let games = games.clone();
+    let referee = thread::spawn(move || {
+        let mut referee = Referee::new(id, tokens, hub, events, grace);
+        for player in players {
+            referee.sit(player);
+        }
+        let winner = referee.run(received);
+        games.lock().unwrap().games.remove(&id);
+        winner
+    });
+
+    (id, tokens, referee)
+}
+
+/// Reads from `session` on a thread of its own, sending what it receives to `events` with
+/// `event`, and then `left` once the connection is gone.
+pub fn forward<E: Send + 'static>(
+    session: &Session,
+    events: Sender<E>,
+    event: impl Fn(Message) -> E + Send + 'static,
+    left: E,
+) {
+    let Ok(mut reader) = session.try_clone() else {
+        let _ = events.send(left);
+        return;
+    };
+
+    thread::spawn(move || {
+        while let Ok(message) = reader.recv() {
+            if events.send(event(message)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(left);
+    });
+}
+
+/// Lets a spectator watch the game it asked for, if it is being played.
+pub fn add_spectator(mut session: Session, games: &Games) {
+    let Ok(id) = spectate::accept_spectator(&mut session) else {
+        return;
+    };
+
+    let hub = {
+        let games = games.lock().unwrap();
+        match id {
+            0 => games
+                .games
+                .values()
+                .next_back()
+                .map(|entry| entry.hub.clone()),
+            id => games.games.get(&id).map(|entry| entry.hub.clone()),
+        }
+    };
+
+    match hub {
+        Some(hub)
+            if session
+                .send(&Message::Welcome { side: Piece::None })
+                .is_ok() =>
+        {
+            hub.add(session);
+        }
+        Some(_) => {}
+        None if id == 0 => {
+            let _ = session.reject("no games are being played right now");
+        }
+        None => {
+            let _ = session.reject(&format!("game {id} is not being played"));
+        }
+    }
+}
+
+/// Hands a returning player back to their game, if they know its secret token.
+pub fn rejoin(mut session: Session, from: SocketAddr, games: &Games) {
+    let Ok(Message::Rejoin { game, token }) = session.recv() else {
+        let _ = session.reject("expected a rejoin code");
+        return;
+    };
+
+    let games = games.lock().unwrap();
+    let Some(entry) = games.games.get(&game) else {
+        let _ = session.reject(&format!("game {game} is already over"));
+        return;
+    };
+    let Some(i) = entry.tokens.iter().position(|t| *t == token) else {
+        let _ = session.reject("that rejoin code is wrong");
+        return;
+    };
+
+    session.side = if i == 0 { Piece::X } else { Piece::O };
+    if session
+        .send(&Message::Welcome { side: session.side })
+        .is_ok()
+    {
+        session.set_timeout(None);
+        println!("{from} rejoined game {game} as {}", session.side);
+        let _ = entry.events.send(Event::Rejoined(session));
+    }
+}
+
+/// One player's place in a refereed game. A seat that was never taken counts as left when the
+/// game starts.
+struct Seat {
+    session: Option<Session>,
+    connection: u64,
+    left_at: Option<Instant>,
+}
+
+impl Default for Seat {
+    fn default() -> Self {
+        Self {
+            session: None,
+            connection: 0,
+            left_at: Some(Instant::now()),
+        }
+    }
+}
+
+/// Plays out one game between two connected players, checking every move against the rules.
+struct Referee {
+    id: u32,
+    game: GameState,
+    history: Vec<(usize, usize)>,
+    tokens: [u64; 2],
+    seats: [Seat; 2],
+    connections: u64,
+    events: Sender<Event>,
+    hub: Arc<Hub>,
+    grace: Duration,
+}
+
+impl Referee {
+    fn new(
+        id: u32,
+        tokens: [u64; 2],
+        hub: Arc<Hub>,
+        events: Sender<Event>,
+        grace: Duration,
+    ) -> Self {
+        Self {
+            id,
+            game: GameState::new(Arc::new(Human), Arc::new(Human)),
+            history: Vec::new(),
+            tokens,
+            seats: Default::default(),
+            connections: 0,
+            events,
+            hub,
+            grace,
+        }
+    }
+
+    /// Gives `session` the seat of its side, reading from it on a thread of its own.
+    fn sit(&mut self, session: Session) {
+        self.connections += 1;
+        let connection = self.connections;
+
+        forward(
+            &session,
+            self.events.clone(),
+            move |message| Event::Message(connection, message),
+            Event::Left(connection),
+        );
+
+        let seat = &mut self.seats[seat(session.side)];
+        if let Some(old) = seat.session.replace(session) {
+            old.close();
+        }
+        seat.connection = connection;
+        seat.left_at = None;
+    }
+
+    fn side_of(&self, connection: u64) -> Option<Piece> {
+        let i = self
+            .seats
+            .iter()
+            .position(|seat| seat.connection == connection && seat.session.is_some())?;
+        Some(if i == 0 { Piece::X } else { Piece::O })
+    }
+
+    fn send(&mut self, side: Piece, message: &Message) {
+        if let Some(session) = &mut self.seats[seat(side)].session {
+            let _ = session.send(message);
+        }
+    }
+
+    fn broadcast(&mut self, message: &Message) {
+        for seat in &mut self.seats {
+            if let Some(session) = &mut seat.session {
+                let _ = session.send(message);
+            }
+        }
+        self.hub.publish(message);
+    }
+
+    fn joined(&self, side: Piece) -> Message {
+        Message::Joined {
+            game: self.id,
+            token: self.tokens[seat(side)],
+            grace: self.grace.as_secs() as u16,
+        }
+    }
+
+    fn run(&mut self, events: Receiver<Event>) -> Piece {
+        println!("Game {} started", self.id);
+        self.broadcast(&Message::Notice(format!(
+            "Game {} started, others can watch with `tut3 watch`.",
+            self.id
+        )));
+        for side in [Piece::X, Piece::O] {
+            self.send(side, &self.joined(side));
+        }
+        self.broadcast(&Message::State(self.game.update_to_bytes()));
+
+        while !self.game.is_complete() {
+            match events.recv_timeout(TICK) {
+                Ok(Event::Message(connection, message)) => {
+                    if let Some(side) = self.side_of(connection) {
+                        self.handle(side, message);
+                    }
+                }
+                Ok(Event::Left(connection)) => {
+                    if let Some(side) = self.side_of(connection) {
+                        self.leave(side);
+                    }
+                }
+                Ok(Event::Rejoined(session)) => self.resume(session),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            for side in [Piece::X, Piece::O] {
+                let left_at = self.seats[seat(side)].left_at;
+                if !self.game.is_complete() && left_at.is_some_and(|at| at.elapsed() >= self.grace)
+                {
+                    self.game.resigned = side;
+                    self.broadcast(&Message::Notice(format!(
+                        "{side} did not come back and forfeits the game."
+                    )));
+                }
+            }
+        }
+
+        let winner = self.game.won();
+        self.broadcast(&Message::GameOver { winner });
+        if winner == Piece::None {
+            println!("Game {} ended in a draw", self.id);
+        } else {
+            println!("Game {} was won by {winner}", self.id);
+        }
+
+        for seat in &self.seats {
+            if let Some(session) = &seat.session {
+                session.close();
+            }
+        }
+        winner
+    }
+
+    fn handle(&mut self, side: Piece, message: Message) {
+        match message {
+            Message::Move { seq, pos } => {
+                let legal = if side != self.game.turn || seq != pieces_placed(&self.game.game) {
+                    Err("It's not your turn!")
+                } else {
+                    check_move(&self.game.game, &self.game.active, pos)
+                };
+
+                if let Err(reason) = legal {
+                    self.send(side, &Message::Illegal(reason.to_string()));
+                    return;
+                }
+
+                self.game.manual_turn(pos.0, pos.1);
+                self.history.push(pos);
+                println!("Game {}: {side} played {}", self.id, pos_as_string(&pos));
+                self.broadcast(&Message::State(self.game.update_to_bytes()));
+            }
+            Message::Resign => {
+                self.game.resigned = side;
+                self.broadcast(&Message::Notice(format!("{side} resigned.")));
+            }
+            Message::Chat { text, .. } => {
+                let chat = Message::Chat { from: side, text };
+                self.send(side.other(), &chat);
+                self.hub.publish(&chat);
+            }
+            Message::Bye => self.leave(side),
+            other => {
+                let reason = format!("Expected a move, not {other:?}");
+                self.send(side, &Message::Illegal(reason));
+            }
+        }
+    }
+
+    fn leave(&mut self, side: Piece) {
+        let seat = &mut self.seats[seat(side)];
+        if let Some(session) = seat.session.take() {
+            session.close();
+        }
+        seat.left_at = Some(Instant::now());
+
+        println!("Game {}: {side} left", self.id);
+        self.broadcast(&Message::Notice(format!(
+            "{side} lost their connection and has {} seconds to come back.",
+            self.grace.as_secs()
+        )));
+    }
+
+    /// Seats a player who rejoined, or joined late, and catches them up on what they missed.
+    fn resume(&mut self, session: Session) {
+        let side = session.side;
+        let returning = self.seats[seat(side)].connection != 0;
+        self.sit(session);
+
+        if !self.history.is_empty() {
+            let moves = Message::Resume {
+                moves: self.history.clone(),
+            };
+            self.send(side, &moves);
+        }
+        self.send(side, &self.joined(side));
+        if returning {
+            self.broadcast(&Message::Notice(format!("{side} is back.")));
+        } else {
+            self.broadcast(&Message::Notice(format!("{side} joined.")));
+        }
+        self.send(side, &Message::State(self.game.update_to_bytes()));
+    }
+}
+
+/// A game number and secret token, which let a player rejoin a game on a server.
+#[derive(Clone, Copy)]
+pub struct RejoinCode {
+    pub game: u32,
+    pub token: u64,
+}
+
+impl RejoinCode {
+    pub fn parse(code: &str) -> Option<RejoinCode> {
+        let (game, token) = code.split_once('-')?;
+        Some(RejoinCode {
+            game: game.parse().ok()?,
+            token: u64::from_str_radix(token, 16).ok()?,
+        })
+    }
+}
+
+impl std::fmt::Display for RejoinCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{:016x}", self.game, self.token)
+    }
+}
+
+/// Connects to the server at `server`, either as a new player or to rejoin a game.
+fn open(
+    server: SocketAddr,
+    side: Piece,
+    rejoin: Option<RejoinCode>,
+    key: Option<&Key>,
+) -> Result<Session, NetError> {
+    let stream = TcpStream::connect(server)?;
+    match rejoin {
+        None => Session::join(stream, Role::Player, side, key),
+        Some(code) => Session::join_with(stream, Role::Returning, side, key, |session| {
+            session.send(&Message::Rejoin {
+                game: code.game,
+                token: code.token,
+            })
+        }),
+    }
+}
+
+/// Keeps trying to rejoin a game until the server says no or `grace` runs out.
+fn reconnect(
+    server: SocketAddr,
+    code: RejoinCode,
+    grace: Duration,
+    key: Option<&Key>,
+) -> Option<Session> {
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        println!("Trying to rejoin game {}...", code.game);
+        match open(server, Piece::None, Some(code), key) {
+            Ok(session) => return Some(session),
+            Err(NetError::Rejected(reason)) => {
+                println!("Could not rejoin: {reason}");
+                return None;
+            }
+            Err(_) => thread::sleep(RECONNECT_INTERVAL),
+        }
+    }
+    None
+}
+
+/// Plays a game refereed by the server at `addr`, with `player` making the moves. `side` is the
+/// piece this player would like, if the server has a choice, and `rejoin` is the code of a game
+/// this player lost the connection to. `key` is needed if the server has a password.
+pub fn connect(
+    addr: &str,
+    side: Piece,
+    rejoin: Option<RejoinCode>,
+    player: Player,
+    key: Option<Key>,
+) {
+    let server = resolve(addr).unwrap_or_else(|| panic!("Could not find server {addr}"));
+    let mut session = open(server, side, rejoin, key.as_ref())
+        .unwrap_or_else(|e| panic!("Failed to join {server}: {e}"));
+    println!("You are playing as {}.", session.side);
+
+    let mut code = rejoin;
+    let mut grace = Duration::ZERO;
+    let mut game = GameState::new(Arc::new(Human), Arc::new(Human));
+    loop {
+        let message = match session.recv() {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Lost connection to the server: {e}");
+                match code.and_then(|code| reconnect(server, code, grace, key.as_ref())) {
+                    Some(rejoined) => {
+                        session = rejoined;
+                        continue;
+                    }
+                    None => return,
+                }
+            }
+        };
+
+        match message {
+            Message::State(state) => {
+                if let Err(e) = game.update_from_bytes(&state) {
+                    println!("The server sent a broken board: {e}");
+                    return;
+                }
+
+                game.print();
+                if let Some(code) = code {
+                    println!("If you lose the connection, rejoin with `--rejoin {code}`");
+                }
+
+                if game.is_complete() {
+                    continue;
+                } else if game.turn == session.side {
+                    send_turn(&mut session, &mut game, &player);
+                } else {
+                    println!("Waiting for {} to move...", game.turn);
+                }
+            }
+            Message::Illegal(reason) => {
+                println!("Invalid move! {reason}");
+                send_turn(&mut session, &mut game, &player);
+            }
+            Message::Notice(text) => println!("{text}"),
+            Message::Chat { from, text } => println!("{}", game.add_chat(from, &text)),
+            Message::Joined {
+                game,
+                token,
+                grace: seconds,
+            } => {
+                code = Some(RejoinCode { game, token });
+                grace = Duration::from_secs(seconds as u64);
+            }
+            Message::Resume { moves } => {
+                let moves = moves.iter().map(pos_as_string).collect::<Vec<_>>();
+                println!("Rejoined the game. Moves so far: {}", moves.join(" "));
+            }
+            Message::GameOver { winner } => {
+                record_winner(&mut game, winner);
+                print_result(&game);
+                let _ = session.send(&Message::Bye);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Asks `player` for a move and sends it to the server, along with anything they say first.
+fn send_turn(session: &mut Session, game: &mut GameState, player: &Player) {
+    let message = loop {
+        match player.play(&game.game, &game.turn, game.active) {
+            Some(Action::Move(pos)) => {
+                break Message::Move {
+                    seq: pieces_placed(&game.game),
+                    pos,
+                };
+            }
+            Some(Action::Resign) => break Message::Resign,
+            Some(Action::Say(text)) => {
+                println!("{}", game.add_chat(session.side, &text));
+                let chat = Message::Chat {
+                    from: session.side,
+                    text,
+                };
+                if let Err(e) = session.send(&chat) {
+                    println!("Failed to send message: {e}");
+                }
+            }
+            None => println!("Invalid move! That position is not within the game boundaries!"),
+        }
+    };
+
+    if let Err(e) = session.send(&message) {
+        println!("Failed to send move: {e}");
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    Action, CHAT_LINES, Game, GameState, Human, Piece, Player, PlayerTrait,
+    auth::Key,
+    net::{Message, NetError, Role, Session, record_winner, resolve},
+    print_result,
+};
+
+/// A spectator that stops reading is dropped rather than allowed to hold up the game.
+const SEND_TIMEOUT: Duration = Duration::from_secs(2);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Everyone watching one game. Spectators that join late are sent the latest chat and board
+/// first.
+#[derive(Default)]
+pub struct Hub {
+    spectators: Mutex<Vec<Session>>,
+    state: Mutex<Option<Vec<u8>>>,
+    chat: Mutex<Vec<Message>>,
+}
+
+impl Hub {
+    pub fn add(&self, mut spectator: Session) {
+        spectator.set_write_timeout(Some(SEND_TIMEOUT));
+
+        for chat in self.chat.lock().unwrap().iter() {
+            if spectator.send(chat).is_err() {
+                return;
+            }
+        }
+        if let Some(state) = self.state.lock().unwrap().clone()
+            && spectator.send(&Message::State(state)).is_err()
+        {
+            return;
+        }
+        self.spectators.lock().unwrap().push(spectator);
+    }
+
+    pub fn publish(&self, message: &Message) {
+        match message {
+            Message::State(state) => *self.state.lock().unwrap() = Some(state.clone()),
+            Message::Chat { .. } => {
+                let mut chat = self.chat.lock().unwrap();
+                if chat.len() == CHAT_LINES {
+                    chat.remove(0);
+                }
+                chat.push(message.clone());
+            }
+            _ => {}
+        }
+
+        self.spectators
+            .lock()
+            .unwrap()
+            .retain_mut(|spectator| spectator.send(message).is_ok());
+    }
+
+    /// Sends the board after a move, or the result if that move ended the game.
+    pub fn publish_game(&self, game: &GameState) {
+        self.publish(&Message::State(game.update_to_bytes()));
+
+        if game.is_complete() {
+            if game.resigned != Piece::None {
+                self.publish(&Message::Notice(format!("{} resigned.", game.resigned)));
+            }
+            self.publish(&Message::GameOver { winner: game.won() });
+        }
+    }
+}
+
+/// A player in a game played on this machine, whose chat is passed on to the spectators.
+pub struct Spectated {
+    pub player: Player,
+    pub hub: Arc<Hub>,
+}
+
+impl PlayerTrait for Spectated {
+    fn play(&self, game: &Game, turn: &Piece, active: Option<(usize, usize)>) -> Option<Action> {
+        let action = self.player.play(game, turn, active)?;
+        if let Action::Say(text) = &action {
+            self.hub.publish(&Message::Chat {
+                from: *turn,
+                text: text.clone(),
+            });
+        }
+        Some(action)
+    }
+}
+
+/// Reads which game a new spectator would like to watch, where 0 means the newest one. The caller
+/// still has to answer with either `Welcome` or `Reject`.
+pub fn accept_spectator(session: &mut Session) -> Result<u32, NetError> {
+    session.set_timeout(Some(HANDSHAKE_TIMEOUT));
+    let game = match session.recv()? {
+        Message::Watch { game } => game,
+        other => return Err(NetError::Unexpected(other)),
+    };
+    session.set_timeout(None);
+    Ok(game)
+}
+
+/// Lets spectators watch a game played on this machine, such as `tut3 smart smart`. With a `key`,
+/// only spectators who know the password can watch.
+pub fn listen(port: u16, key: Option<Key>) -> Arc<Hub> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .unwrap_or_else(|e| panic!("Failed to listen for spectators on port {port}: {e}"));
+    let hub = Arc::new(Hub::default());
+
+    let shared = hub.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+            let Ok((mut session, role, _)) = Session::accept(stream, key.as_ref()) else {
+                continue;
+            };
+
+            if role != Role::Spectator {
+                let _ = session.reject("this game can only be watched, use `tut3 watch`");
+                continue;
+            }
+
+            if accept_spectator(&mut session).is_ok()
+                && session
+                    .send(&Message::Welcome { side: Piece::None })
+                    .is_ok()
+            {
+                shared.add(session);
+            }
+        }
+    });
+
+    hub
+}
+
+/// Watches game number `game_id` (0 for the newest one) on the server or machine at `addr`.
+pub fn watch(addr: &str, game_id: u32, key: Option<Key>) {
+    let server = resolve(addr).unwrap_or_else(|| panic!("Could not find {addr}"));
+    let stream =
+        TcpStream::connect(server).unwrap_or_else(|e| panic!("Failed to connect to {server}: {e}"));
+    let mut session = Session::join_with(
+        stream,
+        Role::Spectator,
+        Piece::None,
+        key.as_ref(),
+        |session| session.send(&Message::Watch { game: game_id }),
+    )
+    .unwrap_or_else(|e| panic!("Failed to watch a game at {server}: {e}"));
+
+    println!("Waiting for the game to start...");
+    let mut game = GameState::new(Arc::new(Human), Arc::new(Human));
+    loop {
+        let message = match session.recv() {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Lost connection to the game: {e}");
+                return;
+            }
+        };
+
+        match message {
+            Message::State(state) => {
+                if let Err(e) = game.update_from_bytes(&state) {
+                    println!("Received a broken board: {e}");
+                    return;
+                }
+
+                game.print();
+                if !game.is_complete() {
+                    println!("Watching, it's {}'s turn.", game.turn);
+                }
+            }
+            Message::Notice(text) => println!("{text}"),
+            Message::Chat { from, text } => println!("{}", game.add_chat(from, &text)),
+            Message::GameOver { winner } => {
+                record_winner(&mut game, winner);
+                print_result(&game);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
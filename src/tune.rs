@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use rand::Rng;
+
+use crate::{
+    Piece,
+    board::Board,
+    eval::{FEATURES, Weights},
+    minimax::{Budget, Level, Minimax},
+    new_game,
+};
+
+/// How many positions each move of a tuning game searches, unless `--nodes` says otherwise.
+pub const NODES: u64 = 20_000;
+/// How far each weight is moved either way to make the two versions that play each other.
+const STEPS: [f64; FEATURES] = [20.0, 20.0, 20.0, 1.0, 10.0, 5.0, 5.0];
+/// Random moves played before each pair of games, so that the games aren't all the same.
+const OPENING_MOVES: usize = 2;
+
+/// Improves `start` by self-play, one round at a time (simultaneous perturbation, or SPSA). Each
+/// round, every weight is nudged a step up or down at random, and the nudged weights play the
+/// weights nudged the other way twice, once as each side. The weights then move towards whichever
+/// did better. The weights so far are written to `out` after every round, so tuning can be
+/// stopped at any time.
+pub fn tune(start: Weights, rounds: usize, budget: Budget, out: &Path) {
+    let mut weights = start.0.map(|weight| weight as f64);
+    let mut rng = rand::rng();
+
+    for round in 1..=rounds {
+        let signs: [f64; FEATURES] =
+            std::array::from_fn(|_| if rng.random_bool(0.5) { 1.0 } else { -1.0 });
+        let nudged = |sign: f64| {
+            Weights(std::array::from_fn(|i| {
+                (weights[i] + sign * signs[i] * STEPS[i]).round() as isize
+            }))
+        };
+        let plus = Minimax::new(budget, Level::EXPERT, nudged(1.0));
+        let minus = Minimax::new(budget, Level::EXPERT, nudged(-1.0));
+
+        // one point for each game the weights nudged up win, and minus one for each they lose
+        let opening = random_opening(&mut rng);
+        let mut result = 0.0;
+        for side in [Piece::X, Piece::O] {
+            let winner = if side == Piece::X {
+                play_game(opening, &plus, &minus)
+            } else {
+                play_game(opening, &minus, &plus)
+            };
+            if winner == side {
+                result += 1.0;
+            } else if winner != Piece::None {
+                result -= 1.0;
+            }
+        }
+
+        // winning both games moves a whole step
+        for i in 0..FEATURES {
+            weights[i] += signs[i] * STEPS[i] * result / 2.0;
+        }
+
+        let tuned = Weights(weights.map(|weight| weight.round() as isize));
+        println!("Round {round}/{rounds}: {result:+}");
+        fs::write(out, tuned.to_string())
+            .unwrap_or_else(|e| panic!("Failed to write {}: {e}", out.display()));
+    }
+
+    println!("Tuned weights, saved to {}:", out.display());
+    print!("{}", Weights(weights.map(|weight| weight.round() as isize)));
+}
+
+fn random_opening(rng: &mut impl Rng) -> Board {
+    let mut board = Board::new(&new_game(), Piece::X, None);
+    for _ in 0..OPENING_MOVES {
+        if let Some(pos) = board.random_move(rng) {
+            board.apply_move(pos);
+        }
+    }
+    board
+}
+
+/// Plays a game from `board` without showing it, and returns who won, or `Piece::None` for a draw.
+fn play_game(mut board: Board, x: &Minimax, o: &Minimax) -> Piece {
+    while !board.is_over() {
+        let bot = if board.turn() == Piece::X { x } else { o };
+        match bot.pick_move(&board, false) {
+            Some(pos) => {
+                board.apply_move(pos);
+            }
+            None => break,
+        }
+    }
+    board.winner()
+}